@@ -0,0 +1,3 @@
+mod ray_tracing;
+
+pub use ray_tracing::*;
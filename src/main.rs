@@ -1,17 +1,18 @@
-mod ray_tracing;
-
+use std::env;
 use std::io::{stderr, stdout, Write};
 
 use std::error::Error;
 use std::result::Result;
+use std::sync::Arc;
 
-use crate::ray_tracing::camera::*;
-use crate::ray_tracing::color::*;
-use crate::ray_tracing::geom::*;
-use crate::ray_tracing::material::*;
-use crate::ray_tracing::object::*;
-use crate::ray_tracing::rand::*;
-use crate::ray_tracing::ray::*;
+use ray_tracing::camera::*;
+use ray_tracing::color::*;
+use ray_tracing::geom::*;
+use ray_tracing::material::*;
+use ray_tracing::object::*;
+use ray_tracing::rand::*;
+use ray_tracing::ray::*;
+use ray_tracing::spectrum::*;
 
 use rayon::prelude::*;
 
@@ -42,12 +43,11 @@ fn random_world() -> HittableList {
     let mut world = HittableList::new();
     let mut random = Random::default();
     let material_ground = Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5));
-    world.add(Object::Sphere {
-        center: Point(Vec3::new(0.0, -1000.0, 0.0)),
-        radius: 1000.0,
-        material: material_ground,
-        moving_component: None,
-    });
+    world.add(Object::new_plane(
+        Point(Vec3::new(0.0, 0.0, 0.0)),
+        Vec3::new(0.0, 1.0, 0.0),
+        material_ground,
+    ));
 
     for a in -11..11 {
         for b in -11..11 {
@@ -99,7 +99,7 @@ fn random_world() -> HittableList {
     world.add(Object::Sphere {
         center: Point(Vec3::new(0.0, 1.0, 0.0)),
         radius: 1.0,
-        material: Material::new_dielectric(1.5),
+        material: Material::new_dispersive_dielectric(Ior::bk7()),
         moving_component: None,
     });
     world.add(Object::Sphere {
@@ -118,6 +118,83 @@ fn random_world() -> HittableList {
     world
 }
 
+fn cornell_camera() -> Camera {
+    Camera::new(
+        Point(Vec3::new(278.0, 278.0, -800.0)),
+        Point(Vec3::new(278.0, 278.0, 0.0)),
+        Point(Vec3::new(0.0, 1.0, 0.0)),
+        40.0,
+        ASPECT_RATIO,
+        0.0,
+        10.0,
+        0.0,
+        1.0,
+    )
+}
+
+fn cornell_box() -> HittableList {
+    let mut world = HittableList::new();
+    world.background = Background::Color(Color::zero());
+    let red = Material::new_lambertian(Color::new_rgb(0.65, 0.05, 0.05));
+    let white = Material::new_lambertian(Color::new_rgb(0.73, 0.73, 0.73));
+    let green = Material::new_lambertian(Color::new_rgb(0.12, 0.45, 0.15));
+    let light = Material::new_diffuse_light(Color::new_rgb(15.0, 15.0, 15.0));
+
+    world.add(Object::new_yz_rect(0.0, 555.0, 0.0, 555.0, 555.0, green));
+    world.add(Object::new_yz_rect(0.0, 555.0, 0.0, 555.0, 0.0, red));
+    world.add(Object::new_xz_rect(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        0.0,
+        white.clone(),
+    ));
+    world.add(Object::new_xz_rect(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    ));
+    world.add(Object::new_xy_rect(
+        0.0,
+        555.0,
+        0.0,
+        555.0,
+        555.0,
+        white.clone(),
+    ));
+    // facing down, into the box
+    world.add_light(Object::new_quad(
+        Point(Vec3::new(213.0, 554.0, 227.0)),
+        Vec3::new(130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 105.0),
+        light,
+    ));
+
+    let tall_box = Arc::new(Object::new_box(
+        &Point(Vec3::new(0.0, 0.0, 0.0)),
+        &Point(Vec3::new(165.0, 330.0, 165.0)),
+        white.clone(),
+    ));
+    world.add(Object::new_instance(
+        tall_box,
+        Mat4::translation(&Vec3::new(265.0, 0.0, 295.0)) * Mat4::rotation_y(15.0),
+    ));
+    let short_box = Arc::new(Object::new_box(
+        &Point(Vec3::new(0.0, 0.0, 0.0)),
+        &Point(Vec3::new(165.0, 165.0, 165.0)),
+        white,
+    ));
+    world.add(Object::new_instance(
+        short_box,
+        Mat4::translation(&Vec3::new(130.0, 0.0, 65.0)) * Mat4::rotation_y(-18.0),
+    ));
+    world
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let stdout = stdout();
     let mut out_handle = stdout.lock();
@@ -126,13 +203,19 @@ fn main() -> Result<(), Box<dyn Error>> {
     let samples_per_pixel = 100u32;
     let samples_per_pixel_f = samples_per_pixel as f32;
 
-    let max_depth = 50;
-    let camera = init_camera();
+    let integrator = Integrator {
+        spectral: env::args().any(|arg| arg == "--spectral"),
+        ..Integrator::default()
+    };
+    let (mut world, camera) = match env::args().nth(1).as_deref() {
+        Some("cornell") => (cornell_box(), cornell_camera()),
+        _ => (random_world(), init_camera()),
+    };
     // let mut random = Random::default();
 
     out_handle.write_all(format!("P3\n{} {}\n{}\n", IMAGE_WIDTH, IMAGE_HEIGTH, 255).as_bytes())?;
 
-    let world = random_world();
+    world.build_bvh(0.0, 1.0);
     let inverse_height = 1.0 / (IMAGE_HEIGTH - 1.0);
     let inverse_width = 1.0 / (IMAGE_WIDTH - 1.0);
     let colors_matrix: Vec<Vec<Color>> = (0..IMAGE_HEIGTH as u32)
@@ -149,8 +232,13 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .map(|_| {
                             let u = (i as f32 + (random.random_double())) * inverse_width;
                             let v = (j as f32 + (random.random_double())) * inverse_height;
-                            let ray = camera.ray(u, v, &mut random);
-                            ray.color(&world, max_depth, &mut random)
+                            match camera.ray(u, v, &mut random) {
+                                Some(sample) => {
+                                    &sample.weight
+                                        * &sample.ray.color(&world, &integrator, &mut random)
+                                }
+                                None => Color::zero(),
+                            }
                         })
                         .sum()
                 })
@@ -0,0 +1,378 @@
+use super::geom::*;
+
+/// Surfaces of revolution around the `y` axis with closed form
+/// intersections, placed by `Object::Analytic`.
+///
+/// `u` goes around the axis over the sweep and `v` along the profile.
+#[derive(Debug, Clone)]
+pub enum AnalyticShape {
+    /// From `y = 0` to `height`, with discs closing both ends when
+    /// `capped`.
+    Cylinder {
+        radius: f32,
+        height: f32,
+        capped: bool,
+    },
+    /// Base of `radius` at `y = 0`, apex at `height`.
+    Cone { radius: f32, height: f32 },
+    /// Apex at the origin, opening up to `radius` at `height`.
+    Paraboloid { radius: f32, height: f32 },
+    /// Hyperboloid of one sheet, `waist_radius` at `y = 0` widening to
+    /// `end_radius` at `y = ±height / 2`.
+    Hyperboloid {
+        waist_radius: f32,
+        end_radius: f32,
+        height: f32,
+    },
+    /// Tube of `minor_radius` around a circle of `major_radius` in the
+    /// `xz` plane.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+}
+
+/// Intersection with an `AnalyticShape`, in its frame.
+pub struct AnalyticHit {
+    pub t: f32,
+    pub outward_normal: Vec3,
+    pub uv: (f32, f32),
+    pub dp_du: Vec3,
+}
+
+impl AnalyticShape {
+    /// Box around the full shape, whatever the sweep.
+    pub fn bounds(&self) -> Aabb {
+        let (radius, y_min, y_max) = match self {
+            AnalyticShape::Cylinder { radius, height, .. }
+            | AnalyticShape::Cone { radius, height }
+            | AnalyticShape::Paraboloid { radius, height } => (*radius, 0.0, *height),
+            AnalyticShape::Hyperboloid {
+                waist_radius,
+                end_radius,
+                height,
+            } => (waist_radius.max(*end_radius), -height / 2.0, height / 2.0),
+            AnalyticShape::Torus {
+                major_radius,
+                minor_radius,
+            } => (major_radius + minor_radius, -minor_radius, *minor_radius),
+        };
+        Aabb::new(
+            Point(Vec3::new(-radius, y_min, -radius)),
+            Point(Vec3::new(radius, y_max, radius)),
+        )
+        .pad(1e-4)
+    }
+
+    /// First intersection over `[t_min, t_max]` of the ray from `origin`
+    /// along `direction`, keeping `phi_max` radians around the axis.
+    pub fn hit(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+        t_min: f32,
+        t_max: f32,
+        phi_max: f32,
+    ) -> Option<AnalyticHit> {
+        let o = [origin.x as f64, origin.y as f64, origin.z as f64];
+        let d = [direction.x as f64, direction.y as f64, direction.z as f64];
+        let radial_a = d[0] * d[0] + d[2] * d[2];
+        let radial_b = 2.0 * (o[0] * d[0] + o[2] * d[2]);
+        let radial_c = o[0] * o[0] + o[2] * o[2];
+        let roots = match self {
+            AnalyticShape::Cylinder { radius, .. } => {
+                let r = *radius as f64;
+                solve_quadratic(radial_a, radial_b, radial_c - r * r)
+            }
+            AnalyticShape::Cone { radius, height } => {
+                let k2 = (*radius as f64 / *height as f64).powi(2);
+                let h = *height as f64 - o[1];
+                solve_quadratic(
+                    radial_a - k2 * d[1] * d[1],
+                    radial_b + 2.0 * k2 * h * d[1],
+                    radial_c - k2 * h * h,
+                )
+            }
+            AnalyticShape::Paraboloid { radius, height } => {
+                let k = *height as f64 / (*radius as f64).powi(2);
+                solve_quadratic(k * radial_a, k * radial_b - d[1], k * radial_c - o[1])
+            }
+            AnalyticShape::Hyperboloid {
+                waist_radius,
+                end_radius,
+                height,
+            } => {
+                let m = self.hyperboloid_slope(*waist_radius, *end_radius, *height);
+                let a2 = (*waist_radius as f64).powi(2);
+                solve_quadratic(
+                    radial_a - m * d[1] * d[1],
+                    radial_b - 2.0 * m * o[1] * d[1],
+                    radial_c - m * o[1] * o[1] - a2,
+                )
+            }
+            AnalyticShape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let (big, small) = (*major_radius as f64, *minor_radius as f64);
+                let g = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+                let h = 2.0 * (o[0] * d[0] + o[1] * d[1] + o[2] * d[2]);
+                let i = o[0] * o[0] + o[1] * o[1] + o[2] * o[2] + big * big - small * small;
+                let four_r2 = 4.0 * big * big;
+                solve_quartic([
+                    g * g,
+                    2.0 * g * h,
+                    h * h + 2.0 * g * i - four_r2 * radial_a,
+                    2.0 * h * i - four_r2 * radial_b,
+                    i * i - four_r2 * radial_c,
+                ])
+            }
+        };
+        let mut best: Option<AnalyticHit> = None;
+        for t in roots {
+            let t = t as f32;
+            if t < t_min || t > t_max {
+                continue;
+            }
+            let p = origin + &direction.scalar_mul(t);
+            if let Some(hit) = self.surface_hit(&p, t, phi_max) {
+                best = Some(hit);
+                break;
+            }
+        }
+        if let AnalyticShape::Cylinder {
+            radius,
+            height,
+            capped: true,
+        } = self
+        {
+            for y in [0.0, *height] {
+                if direction.y == 0.0 {
+                    break;
+                }
+                let t = (y - origin.y) / direction.y;
+                let closer = best.as_ref().is_none_or(|hit| t < hit.t);
+                if t < t_min || t > t_max || !closer {
+                    continue;
+                }
+                let p = origin + &direction.scalar_mul(t);
+                let rho = (p.x * p.x + p.z * p.z).sqrt();
+                let phi = azimuth(&p);
+                if rho > *radius || phi > phi_max {
+                    continue;
+                }
+                let up = if y > 0.0 { 1.0 } else { -1.0 };
+                best = Some(AnalyticHit {
+                    t,
+                    outward_normal: Vec3::new(0.0, up, 0.0),
+                    uv: (phi / phi_max, rho / radius),
+                    dp_du: Vec3::new(-p.z, 0.0, p.x),
+                });
+            }
+        }
+        best
+    }
+
+    // `a² / c²` of `x² + z² = a² (1 + y² / c²)`
+    fn hyperboloid_slope(&self, waist_radius: f32, end_radius: f32, height: f32) -> f64 {
+        let half = height as f64 / 2.0;
+        ((end_radius as f64).powi(2) - (waist_radius as f64).powi(2)) / (half * half)
+    }
+
+    // point on the implicit surface if it is within the bounds of the shape
+    fn surface_hit(&self, p: &Vec3, t: f32, phi_max: f32) -> Option<AnalyticHit> {
+        let phi = azimuth(p);
+        if phi > phi_max {
+            return None;
+        }
+        let (normal, v) = match self {
+            AnalyticShape::Cylinder { height, .. } => {
+                if !(0.0..=*height).contains(&p.y) {
+                    return None;
+                }
+                (Vec3::new(p.x, 0.0, p.z), p.y / height)
+            }
+            AnalyticShape::Cone { radius, height } => {
+                if !(0.0..=*height).contains(&p.y) {
+                    return None;
+                }
+                let k2 = (radius / height).powi(2);
+                (Vec3::new(p.x, k2 * (height - p.y), p.z), p.y / height)
+            }
+            AnalyticShape::Paraboloid { radius, height } => {
+                if !(0.0..=*height).contains(&p.y) {
+                    return None;
+                }
+                let k = height / (radius * radius);
+                (Vec3::new(k * p.x, -0.5, k * p.z), p.y / height)
+            }
+            AnalyticShape::Hyperboloid {
+                waist_radius,
+                end_radius,
+                height,
+            } => {
+                let half = height / 2.0;
+                if !(-half..=half).contains(&p.y) {
+                    return None;
+                }
+                let m = self.hyperboloid_slope(*waist_radius, *end_radius, *height) as f32;
+                (Vec3::new(p.x, -m * p.y, p.z), (p.y + half) / height)
+            }
+            AnalyticShape::Torus { major_radius, .. } => {
+                let rho = (p.x * p.x + p.z * p.z).sqrt();
+                // from the centre of the tube
+                let ring = Vec3::new(p.x / rho * major_radius, 0.0, p.z / rho * major_radius);
+                let normal = p - &ring;
+                let theta = normal.y.atan2(rho - major_radius);
+                (normal, (theta + PI) / (2.0 * PI))
+            }
+        };
+        Some(AnalyticHit {
+            t,
+            outward_normal: normal.unit_norm(),
+            uv: (phi / phi_max, v),
+            dp_du: Vec3::new(-p.z, 0.0, p.x),
+        })
+    }
+}
+
+// angle around the `y` axis in `[0, 2π)`, from `+x` towards `+z`
+fn azimuth(p: &Vec3) -> f32 {
+    let phi = p.z.atan2(p.x);
+    if phi < 0.0 {
+        phi + 2.0 * PI
+    } else {
+        phi
+    }
+}
+
+/// Real roots of `a t² + b t + c` in increasing order.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < 1e-12 {
+        return if b == 0.0 { vec![] } else { vec![-c / b] };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return vec![];
+    }
+    // avoids cancellation between `b` and the root
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    if t0 < t1 {
+        vec![t0, t1]
+    } else {
+        vec![t1, t0]
+    }
+}
+
+// real roots of the monic `t³ + a t² + b t + c`
+fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    if r * r < q * q * q {
+        let theta = (r / (q * q * q).sqrt()).clamp(-1.0, 1.0).acos();
+        let s = -2.0 * q.sqrt();
+        (0..3)
+            .map(|k| s * ((theta + 2.0 * std::f64::consts::PI * k as f64) / 3.0).cos() - a / 3.0)
+            .collect()
+    } else {
+        let e = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+        let f = if e == 0.0 { 0.0 } else { q / e };
+        vec![e + f - a / 3.0]
+    }
+}
+
+/// Real roots of `c[0] t⁴ + c[1] t³ + c[2] t² + c[3] t + c[4]` in
+/// increasing order, by Ferrari's method and polished with Newton steps.
+pub fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    if c[0].abs() < 1e-12 {
+        return solve_quadratic(c[2], c[3], c[4]);
+    }
+    let (a, b, cc, d) = (c[1] / c[0], c[2] / c[0], c[3] / c[0], c[4] / c[0]);
+    // depressed quartic y⁴ + p y² + q y + r with t = y - a / 4
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = cc - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * cc / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+    let mut roots = if q.abs() < 1e-12 {
+        // biquadratic
+        solve_quadratic(1.0, p, r)
+            .into_iter()
+            .filter(|z| *z >= 0.0)
+            .flat_map(|z| [z.sqrt(), -z.sqrt()])
+            .collect::<Vec<_>>()
+    } else {
+        // largest root of the resolvent cubic splits it into two quadratics
+        let m = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0)
+            .into_iter()
+            .fold(f64::MIN, f64::max);
+        if m <= 0.0 {
+            return vec![];
+        }
+        let s = (2.0 * m).sqrt();
+        let mut roots = solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s));
+        roots.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+        roots
+    };
+    for t in roots.iter_mut() {
+        *t -= a / 4.0;
+        for _ in 0..2 {
+            let f = (((c[0] * *t + c[1]) * *t + c[2]) * *t + c[3]) * *t + c[4];
+            let df = ((4.0 * c[0] * *t + 3.0 * c[1]) * *t + 2.0 * c[2]) * *t + c[3];
+            if df != 0.0 {
+                *t -= f / df;
+            }
+        }
+    }
+    roots.sort_by(f64::total_cmp);
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve_quartic() {
+        // (t - 1)(t - 2)(t + 3)(t - 0.5)
+        let roots = solve_quartic([1.0, -0.5, -7.0, 9.5, -3.0]);
+        let expected = [-3.0, 0.5, 1.0, 2.0];
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip(expected.iter()) {
+            assert!((root - expected).abs() < 1e-9, "{:?}", roots);
+        }
+        assert!(solve_quartic([1.0, 0.0, 0.0, 0.0, 1.0]).is_empty());
+    }
+
+    #[test]
+    fn test_torus_and_capped_cylinder() {
+        let torus = AnalyticShape::Torus {
+            major_radius: 2.0,
+            minor_radius: 0.5,
+        };
+        let origin = Vec3::new(-5.0, 0.0, 0.0);
+        let direction = Vec3::new(1.0, 0.0, 0.0);
+        let hit = torus
+            .hit(&origin, &direction, 0.0, INFINITY, 2.0 * PI)
+            .unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-4);
+        assert!((&hit.outward_normal - &Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-4);
+        // a quarter of it, the near side is swept away
+        let quarter = torus
+            .hit(&origin, &direction, 0.0, INFINITY, PI / 2.0)
+            .unwrap();
+        assert!((quarter.t - 6.5).abs() < 1e-4);
+
+        let cylinder = AnalyticShape::Cylinder {
+            radius: 1.0,
+            height: 2.0,
+            capped: true,
+        };
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let hit = cylinder
+            .hit(&Vec3::new(0.5, 5.0, 0.0), &down, 0.0, INFINITY, 2.0 * PI)
+            .unwrap();
+        assert!((hit.t - 3.0).abs() < 1e-4);
+        assert_eq!(hit.outward_normal, Vec3::new(0.0, 1.0, 0.0));
+    }
+}
@@ -0,0 +1,211 @@
+use super::geom::*;
+use super::object::*;
+use super::rand::*;
+use super::ray::*;
+
+/// Bounding volume hierarchy over bounded objects.
+///
+/// As a light, it is sampled by picking one of its objects uniformly.
+pub enum Bvh {
+    Leaf {
+        object: Object,
+        bbox: Aabb,
+    },
+    Node {
+        left: Box<Bvh>,
+        right: Box<Bvh>,
+        bbox: Aabb,
+        /// Objects under the node.
+        leaves: usize,
+    },
+}
+
+impl Bvh {
+    /// Builds the hierarchy splitting on the longest axis of the centroids.
+    /// Returns `None` when there is nothing to build on.
+    ///
+    /// Panics if an object has no bounding box: unbounded surfaces must be
+    /// kept out of the hierarchy.
+    pub fn new(objects: Vec<Object>, time_0: f32, time_1: f32) -> Option<Bvh> {
+        let boxed = objects
+            .into_iter()
+            .map(|object| {
+                let bbox = object
+                    .bounding_box(time_0, time_1)
+                    .expect("unbounded object in BVH");
+                (object, bbox)
+            })
+            .collect();
+        Bvh::build(boxed)
+    }
+
+    fn build(mut objects: Vec<(Object, Aabb)>) -> Option<Bvh> {
+        match objects.len() {
+            0 => None,
+            1 => objects
+                .pop()
+                .map(|(object, bbox)| Bvh::Leaf { object, bbox }),
+            len => {
+                let centroids = objects
+                    .iter()
+                    .map(|(_, bbox)| {
+                        let c = Point(bbox.centroid());
+                        Aabb::new(c.clone(), c)
+                    })
+                    .reduce(|a, b| a.surrounding(&b))?;
+                let axis = centroids.longest_axis();
+                objects.sort_by(|(_, a), (_, b)| {
+                    a.centroid()
+                        .axis(axis)
+                        .partial_cmp(&b.centroid().axis(axis))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+                let right = objects.split_off(len / 2);
+                let left = Bvh::build(objects)?;
+                let right = Bvh::build(right)?;
+                let bbox = left.bbox().surrounding(right.bbox());
+                let leaves = left.leaves() + right.leaves();
+                Some(Bvh::Node {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    bbox,
+                    leaves,
+                })
+            }
+        }
+    }
+
+    pub fn bbox(&self) -> &Aabb {
+        match self {
+            Bvh::Leaf { bbox, .. } => bbox,
+            Bvh::Node { bbox, .. } => bbox,
+        }
+    }
+
+    pub fn leaves(&self) -> usize {
+        match self {
+            Bvh::Leaf { .. } => 1,
+            Bvh::Node { leaves, .. } => *leaves,
+        }
+    }
+
+    // sum of the densities of the objects, skipping the subtrees the
+    // direction misses since sampled objects must be hit
+    fn pdf_sum(&self, origin: &Point, direction: &Vec3, time: f32) -> f32 {
+        if !self.bbox().hit(&origin.0, direction, 0.001, INFINITY) {
+            return 0.0;
+        }
+        match self {
+            Bvh::Leaf { object, .. } => object.pdf_value(origin, direction, time),
+            Bvh::Node { left, right, .. } => {
+                left.pdf_sum(origin, direction, time) + right.pdf_sum(origin, direction, time)
+            }
+        }
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        if !self
+            .bbox()
+            .hit(&ray.origin.0, &ray.direction.0, t_min, t_max)
+        {
+            return None;
+        }
+        match self {
+            Bvh::Leaf { object, .. } => object.hit(ray, t_min, t_max),
+            Bvh::Node { left, right, .. } => {
+                let hit_left = left.hit(ray, t_min, t_max);
+                let closest = hit_left.as_ref().map(|rec| rec.t).unwrap_or(t_max);
+                right.hit(ray, t_min, closest).or(hit_left)
+            }
+        }
+    }
+
+    fn bounding_box(&self, _time_0: f32, _time_1: f32) -> Option<Aabb> {
+        Some(self.bbox().clone())
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, time: f32) -> f32 {
+        self.pdf_sum(origin, direction, time) / self.leaves() as f32
+    }
+
+    fn random_direction(&self, origin: &Point, time: f32, r: &mut Random) -> Option<Vec3> {
+        match self {
+            Bvh::Leaf { object, .. } => object.random_direction(origin, time, r),
+            Bvh::Node { left, right, .. } => {
+                let p_left = left.leaves() as f32 / self.leaves() as f32;
+                if r.random_double() < p_left {
+                    left.random_direction(origin, time, r)
+                } else {
+                    right.random_direction(origin, time, r)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracing::color::Color;
+    use crate::ray_tracing::material::Material;
+
+    fn sphere(center: Vec3, radius: f32) -> Object {
+        Object::Sphere {
+            center: Point(center),
+            radius,
+            material: Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5)),
+            moving_component: None,
+        }
+    }
+
+    #[test]
+    fn test_matches_linear_scan() {
+        let mut r = Random::seeded(1);
+        let spheres: Vec<(Vec3, f32)> = (0..100)
+            .map(|_| {
+                let center = Vec3::random_in_unit_sphere(&mut r).scalar_mul(10.0);
+                (center, 0.2 + r.random_double())
+            })
+            .collect();
+        let objects = spheres.iter().map(|(c, radius)| sphere(c.clone(), *radius));
+        let bvh = Bvh::new(objects.collect(), 0.0, 1.0).unwrap();
+        assert_eq!(bvh.leaves(), 100);
+        let linear: Vec<Object> = spheres
+            .iter()
+            .map(|(c, radius)| sphere(c.clone(), *radius))
+            .collect();
+        for _ in 0..1000 {
+            let origin = Vec3::random_in_unit_sphere(&mut r).scalar_mul(15.0);
+            let direction = Vec3::random_in_unit_sphere(&mut r);
+            let ray = Ray::new(Point(origin), Point(direction), 0.0);
+            let expected = linear
+                .iter()
+                .filter_map(|object| object.hit(&ray, 0.001, INFINITY))
+                .map(|rec| rec.t)
+                .reduce(f32::min);
+            let found = bvh.hit(&ray, 0.001, INFINITY).map(|rec| rec.t);
+            assert_eq!(expected, found);
+        }
+    }
+
+    #[test]
+    fn test_light_sampling() {
+        let light = |x: f32| sphere(Vec3::new(x, 2.0, 0.0), 0.5);
+        let bvh = Bvh::new(vec![light(-2.0), light(2.0)], 0.0, 1.0).unwrap();
+        let origin = Point(Vec3::iso(0.0));
+        // the average of both, one of which is missed
+        let direction = Vec3::new(2.0, 2.0, 0.0);
+        let pdf = light(2.0).pdf_value(&origin, &direction, 0.0);
+        assert!(pdf > 0.0);
+        assert!((bvh.pdf_value(&origin, &direction, 0.0) - 0.5 * pdf).abs() < 1e-6);
+        // both are sampled about as often
+        let mut r = Random::seeded(1);
+        let right = (0..10_000)
+            .filter_map(|_| bvh.random_direction(&origin, 0.0, &mut r))
+            .filter(|direction| direction.x > 0.0)
+            .count();
+        assert!((4500..5500).contains(&right), "{}", right);
+    }
+}
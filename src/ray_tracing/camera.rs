@@ -1,21 +1,373 @@
+use super::color::*;
 use super::geom::*;
+use super::image::Image;
+use super::motion::*;
+use super::rand::*;
 use super::ray::*;
-use crate::Random;
+
+/// Where the camera is and where it looks at `time`.
+#[derive(Debug, Clone)]
+pub struct CameraPose {
+    pub time: f32,
+    pub look_from: Point,
+    pub look_at: Point,
+    pub view_up: Point,
+}
+
+impl CameraPose {
+    // camera space looks down -z with +y up, this moves it to the pose
+    fn keyframe(&self) -> Keyframe {
+        let w = (&self.look_from.0 - &self.look_at.0).unit_norm();
+        let u = self.view_up.0.cross(&w).unit_norm();
+        let v = w.cross(&u);
+        let basis = Mat4([
+            [u.x, v.x, w.x, 0.0],
+            [u.y, v.y, w.y, 0.0],
+            [u.z, v.z, w.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Keyframe::new(
+            self.time,
+            self.look_from.0.clone(),
+            Quat::from_mat4(&basis),
+            Vec3::iso(1.0),
+        )
+    }
+}
+
+/// Shutter efficiency over the time it is open.
+pub enum ShutterCurve {
+    /// Fully open for the whole interval.
+    Box,
+    /// Opens linearly up to the middle of the interval, then closes.
+    Triangular,
+    /// Piecewise constant efficiency, see [`ShutterCurve::custom`].
+    Custom { cdf: Vec<f32> },
+}
+
+impl ShutterCurve {
+    /// Efficiency given by `weights` evenly spaced over the open interval.
+    /// Panics if all of them are zero.
+    pub fn custom(weights: &[f32]) -> ShutterCurve {
+        let total: f32 = weights.iter().map(|w| w.max(0.0)).sum();
+        assert!(total > 0.0, "shutter curve must open at some point");
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        cdf.push(0.0);
+        let mut acc = 0.0;
+        for w in weights {
+            acc += w.max(0.0) / total;
+            cdf.push(acc);
+        }
+        ShutterCurve::Custom { cdf }
+    }
+
+    // fraction of the open interval, distributed as the efficiency
+    fn sample(&self, r: &mut Random) -> f32 {
+        match self {
+            ShutterCurve::Box => r.random_double(),
+            ShutterCurve::Triangular => 0.5 * (r.random_double() + r.random_double()),
+            ShutterCurve::Custom { cdf } => {
+                let u = r.random_double();
+                let segments = cdf.len() - 1;
+                let i = (cdf.partition_point(|c| *c <= u).max(1) - 1).min(segments - 1);
+                let width = cdf[i + 1] - cdf[i];
+                let offset = if width > 0.0 {
+                    (u - cdf[i]) / width
+                } else {
+                    0.0
+                };
+                (i as f32 + offset) / segments as f32
+            }
+        }
+    }
+}
+
+pub struct Shutter {
+    pub open: f32,
+    pub close: f32,
+    pub curve: ShutterCurve,
+    /// Time the sensor takes to read out from the top to the bottom row,
+    /// zero for a global shutter. It fits in the shutter interval, so that
+    /// scenes built over it stay valid: each row is exposed for
+    /// `close - open - readout_time`, starting `readout_time` times its
+    /// distance from the top later than `open`.
+    pub readout_time: f32,
+}
+
+impl Shutter {
+    pub fn new(open: f32, close: f32) -> Shutter {
+        Shutter {
+            open,
+            close,
+            curve: ShutterCurve::Box,
+            readout_time: 0.0,
+        }
+    }
+
+    /// Time of a sample on the row at vertical image coordinate `t`.
+    pub fn sample_time(&self, t: f32, r: &mut Random) -> f32 {
+        let interval = self.close - self.open;
+        let readout = self.readout_time.clamp(0.0, interval);
+        let row_open = self.open + readout * (1.0 - t);
+        row_open + (interval - readout) * self.curve.sample(r)
+    }
+}
+
+/// How directions around the camera map to the image.
+pub enum Projection {
+    /// Thin lens perspective, field of view in degrees.
+    Perspective { vertical_fov: f32 },
+    /// Parallel rays through a viewport `height` world units tall.
+    Orthographic { height: f32 },
+    /// Equidistant fisheye: the angle from the view direction grows
+    /// linearly with the distance from the image centre, reaching `fov / 2`
+    /// degrees on the circle inscribed in the image height. Corners outside
+    /// the circle stay black.
+    Fisheye { fov: f32 },
+    /// Latitude-longitude 360 by 180 degrees panorama, the view direction is
+    /// at the centre of the image. Meant for 2:1 images.
+    Equirectangular,
+    /// Six 90 degree faces laid out in a 3:2 image, looking
+    /// left, forward and right on the top row, back, up and down on the
+    /// bottom one.
+    CubeMap,
+}
+
+/// Shape of the lens opening, hence of out of focus highlights.
+pub enum Aperture {
+    Circle,
+    /// Regular polygon with `blades` sides, rotated by `rotation` degrees.
+    Polygon {
+        blades: u32,
+        rotation: f32,
+    },
+    /// Transmission read from an image, see [`Aperture::from_image`].
+    Image {
+        width: usize,
+        cdf: Vec<f32>,
+    },
+}
+
+impl Aperture {
+    /// Aperture whose transmission is the brightness of `image`, stretched
+    /// over the square enclosing the lens. Panics on a black image.
+    pub fn from_image(image: &Image) -> Aperture {
+        let mut cdf = Vec::with_capacity(image.pixels().len() + 1);
+        cdf.push(0.0);
+        let mut acc = 0.0;
+        for p in image.pixels() {
+            acc += (p.x + p.y + p.z).max(0.0);
+            cdf.push(acc);
+        }
+        assert!(acc > 0.0, "aperture image is black");
+        for c in cdf.iter_mut() {
+            *c /= acc;
+        }
+        Aperture::Image {
+            width: image.width,
+            cdf,
+        }
+    }
+
+    // point on the lens in [-1, 1] x [-1, 1]
+    fn sample(&self, r: &mut Random) -> Vec3 {
+        match self {
+            Aperture::Circle => Vec3::random_in_unit_disk(r),
+            Aperture::Polygon { blades, rotation } => {
+                // uniform in one of the triangles fanning from the centre
+                let blades = (*blades).max(3);
+                let sector = ((r.random_double() * blades as f32) as u32).min(blades - 1);
+                let step = 2.0 * PI / blades as f32;
+                let angle = degrees_to_radians(*rotation) + step * sector as f32;
+                let mut a = r.random_double();
+                let mut b = r.random_double();
+                if a + b > 1.0 {
+                    a = 1.0 - a;
+                    b = 1.0 - b;
+                }
+                Vec3::new(angle.cos(), angle.sin(), 0.0).scalar_mul(a)
+                    + Vec3::new((angle + step).cos(), (angle + step).sin(), 0.0).scalar_mul(b)
+            }
+            Aperture::Image { width, cdf } => {
+                let u = r.random_double();
+                let pixel = (cdf.partition_point(|c| *c <= u).max(1) - 1).min(cdf.len() - 2);
+                let height = (cdf.len() - 1) / width;
+                let x = (pixel % width) as f32 + r.random_double();
+                let y = (pixel / width) as f32 + r.random_double();
+                Vec3::new(
+                    2.0 * x / *width as f32 - 1.0,
+                    1.0 - 2.0 * y / height as f32,
+                    0.0,
+                )
+            }
+        }
+    }
+}
+
+/// Brown-Conrady lens distortion over image coordinates at unit distance
+/// from the lens: `k1` and `k2` radial (negative for barrel, positive for
+/// pincushion), `p1` and `p2` tangential.
+#[derive(Debug, Clone, Default)]
+pub struct Distortion {
+    pub k1: f32,
+    pub k2: f32,
+    pub p1: f32,
+    pub p2: f32,
+}
+
+impl Distortion {
+    fn is_none(&self) -> bool {
+        self.k1 == 0.0 && self.k2 == 0.0 && self.p1 == 0.0 && self.p2 == 0.0
+    }
+
+    /// Where the undistorted `(x, y)` ends up on the image.
+    pub fn distort(&self, x: f32, y: f32) -> (f32, f32) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+        (
+            x * radial + 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x),
+            y * radial + self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y,
+        )
+    }
+
+    /// Inverse of `distort` by fixed point iteration.
+    pub fn undistort(&self, x: f32, y: f32) -> (f32, f32) {
+        let (mut ux, mut uy) = (x, y);
+        for _ in 0..10 {
+            let (dx, dy) = self.distort(ux, uy);
+            ux += x - dx;
+            uy += y - dy;
+        }
+        (ux, uy)
+    }
+}
+
+/// Imperfections of a perspective lens.
+pub struct Lens {
+    pub aperture: Aperture,
+    /// Optical vignetting: the lens barrel clips the aperture more and more
+    /// towards the borders, shifting by `cat_eye` aperture radii at the top
+    /// and bottom of the image. Zero disables it.
+    pub cat_eye: f32,
+    pub distortion: Distortion,
+    /// Lateral chromatic aberration: how much larger the image is in blue
+    /// than in red, relative to green.
+    pub chromatic_aberration: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Lens {
+        Lens {
+            aperture: Aperture::Circle,
+            cat_eye: 0.0,
+            distortion: Distortion::default(),
+            chromatic_aberration: 0.0,
+        }
+    }
+}
+
+/// Camera ray and what its radiance is to be multiplied by.
+pub struct CameraSample {
+    pub ray: Ray,
+    pub weight: Color,
+}
+
+/// How the two eyes share the image.
+pub enum StereoLayout {
+    /// Left eye on the left half.
+    SideBySide,
+    /// Left eye on the top half.
+    TopBottom,
+}
+
+/// Renders both eyes in the same image. The camera aspect ratio is the one
+/// of a single eye, so side by side images are twice as wide.
+///
+/// Panoramic projections use omni-directional stereo: the eyes sit on a
+/// circle and turn with the horizontal view direction, so the whole
+/// panorama stays stereo.
+pub struct Stereo {
+    pub interocular_distance: f32,
+    /// Distance with no parallax between the eyes, nearer objects pop out
+    /// of the screen.
+    pub convergence_distance: f32,
+    pub layout: StereoLayout,
+}
+
+/// Camera settings in photographic units. Sensor and focal lengths are in
+/// millimetres and the scene is taken to be in metres.
+#[derive(Debug, Clone)]
+pub struct PhysicalCamera {
+    pub focal_length: f32,
+    pub f_number: f32,
+    pub sensor_width: f32,
+    pub sensor_height: f32,
+    /// Exposure time in seconds. It only sets the brightness, the motion
+    /// blur interval stays the one of the `Shutter`.
+    pub shutter_speed: f32,
+    pub iso: f32,
+}
+
+impl Default for PhysicalCamera {
+    /// 50mm lens on a full frame sensor, at f/8, 1/125s and ISO 100.
+    fn default() -> PhysicalCamera {
+        PhysicalCamera {
+            focal_length: 50.0,
+            f_number: 8.0,
+            sensor_width: 36.0,
+            sensor_height: 24.0,
+            shutter_speed: 1.0 / 125.0,
+            iso: 100.0,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    /// Exposure value at ISO 100.
+    pub fn ev100(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter_speed * 100.0 / self.iso).log2()
+    }
+
+    /// Factor from scene radiance to image value, using the saturation based
+    /// sensitivity: a radiance of `1.2 * 2^EV100` just saturates the sensor.
+    pub fn exposure(&self) -> f32 {
+        1.0 / (1.2 * self.ev100().exp2())
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.sensor_width / self.sensor_height
+    }
+
+    pub fn vertical_fov(&self) -> f32 {
+        2.0 * (self.sensor_height / (2.0 * self.focal_length))
+            .atan()
+            .to_degrees()
+    }
+
+    /// Diameter of the entrance pupil in metres.
+    pub fn aperture(&self) -> f32 {
+        self.focal_length / self.f_number / 1000.0
+    }
+}
 
 pub struct Camera {
-    origin: Point,
-    lower_left_corner: Point,
-    horizontal: Point,
-    vertical: Point,
-    u: Point,
-    v: Point,
-    w: Point,
+    /// Camera to world, over the shutter interval.
+    pose: AnimatedTransform,
+    projection: Projection,
+    aspect_ratio: f32,
+    // perspective viewport size at unit distance from the lens
+    viewport_width: f32,
+    viewport_height: f32,
+    focus_dist: f32,
     lens_radius: f32,
-    time_start: f32,
-    time_end: f32,
+    shutter: Shutter,
+    stereo: Option<Stereo>,
+    lens: Lens,
+    exposure: f32,
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         look_from: Point,
         look_at: Point,
@@ -30,48 +382,457 @@ impl Camera {
         let theta = degrees_to_radians(vertical_fov);
         let h = (theta / 2.0).tan();
 
-        let w = (&look_from.0 - &look_at.0).unit_norm();
-        let u = view_up.0.cross(&w).unit_norm();
-        let v = w.cross(&u);
-
-        let origin = look_from;
         let viewport_height: f32 = 2.0 * h;
         let viewport_width: f32 = aspect_ratio * viewport_height;
+        let pose = CameraPose {
+            time: time_start,
+            look_from,
+            look_at,
+            view_up,
+        };
 
-        let horizontal: Point = Point(u.scalar_mul(viewport_width * focus_dist));
-        let vertical: Point = Point(v.scalar_mul(viewport_height * focus_dist));
-        let lower_left_corner = Point(
-            &origin.0
-                - &horizontal.0.scalar_div(2.0)
-                - vertical.0.scalar_div(2.0)
-                - w.scalar_mul(focus_dist),
-        );
         Camera {
-            origin,
-            lower_left_corner,
-            horizontal,
-            vertical,
-            u: Point(u),
-            v: Point(v),
-            w: Point(w),
+            pose: AnimatedTransform::new(vec![pose.keyframe()]),
+            projection: Projection::Perspective { vertical_fov },
+            aspect_ratio,
+            viewport_width,
+            viewport_height,
+            focus_dist,
             lens_radius: aperture / 2.0,
-            time_start,
-            time_end,
+            shutter: Shutter::new(time_start, time_end),
+            stereo: None,
+            lens: Lens::default(),
+            exposure: 1.0,
+        }
+    }
+
+    /// Moves the camera through `poses` while the shutter is open, replacing
+    /// the pose given to `new`.
+    pub fn with_motion(mut self, poses: Vec<CameraPose>) -> Camera {
+        let keyframes = poses.iter().map(CameraPose::keyframe).collect();
+        self.pose = AnimatedTransform::new(keyframes);
+        self
+    }
+
+    pub fn with_shutter(mut self, shutter: Shutter) -> Camera {
+        self.shutter = shutter;
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Camera {
+        if let Projection::Perspective { vertical_fov } = projection {
+            self.viewport_height = 2.0 * (degrees_to_radians(vertical_fov) / 2.0).tan();
+            self.viewport_width = self.aspect_ratio * self.viewport_height;
         }
+        self.projection = projection;
+        self
+    }
+
+    pub fn with_stereo(mut self, stereo: Stereo) -> Camera {
+        self.stereo = Some(stereo);
+        self
+    }
+
+    /// Lens effects, only used by the perspective projection.
+    pub fn with_lens(mut self, lens: Lens) -> Camera {
+        self.lens = lens;
+        self
+    }
+
+    /// Replaces the field of view, aspect ratio, aperture and exposure by
+    /// the ones of `physical`. Images should have the aspect ratio of the
+    /// sensor.
+    pub fn with_physical(mut self, physical: &PhysicalCamera) -> Camera {
+        self.aspect_ratio = physical.aspect_ratio();
+        self = self.with_projection(Projection::Perspective {
+            vertical_fov: physical.vertical_fov(),
+        });
+        self.lens_radius = physical.aperture() / 2.0;
+        self.exposure = physical.exposure();
+        self
     }
 
-    pub fn ray(&self, s: f32, t: f32, r: &mut Random) -> Ray {
-        let rd = Vec3::random_in_unit_disk(r).scalar_mul(self.lens_radius);
-        let offset = self.u.0.scalar_mul(rd.x) + self.v.0.scalar_mul(rd.y);
-        Ray::new(
-            &self.origin,
-            Point(
-                &(&(&(&self.lower_left_corner.0 + &self.horizontal.0.scalar_mul(s))
-                    + &self.vertical.0.scalar_mul(t))
-                    - &self.origin.0)
-                    - &offset,
+    /// Focuses on whatever is seen through the image point `(s, t)` at the
+    /// start of the shutter interval. The focus distance is left alone if
+    /// nothing is there.
+    ///
+    /// Panics for projections other than perspective, which have no focus.
+    pub fn with_autofocus(mut self, world: &HittableList, s: f32, t: f32) -> Camera {
+        assert!(
+            matches!(self.projection, Projection::Perspective { .. }),
+            "only perspective cameras focus"
+        );
+        // through the lens centre, the direction has unit depth so the hit
+        // t is the distance to the focus plane
+        let (x, y) = self.image_plane(s, t, 1.0);
+        let direction = Vec3::new(x, y, -1.0);
+        let time = self.shutter.open;
+        let camera_to_world = self.pose.at(time).matrix();
+        let ray = Ray::new(
+            Point(camera_to_world.transform_point(&Vec3::iso(0.0))),
+            Point(camera_to_world.transform_vector(&direction)),
+            time,
+        );
+        if let Some(rec) = world.hit(&ray, 0.001, f32::INFINITY) {
+            self.focus_dist = rec.t;
+        }
+        self
+    }
+
+    /// Ray through the image point `(s, t)`, both in `[0, 1]` from the
+    /// bottom left corner. `None` where the projection covers no direction
+    /// or the lens blocks the ray.
+    pub fn ray(&self, s: f32, t: f32, r: &mut Random) -> Option<CameraSample> {
+        let (origin, direction, weight, t) = match &self.stereo {
+            None => {
+                let (origin, direction, weight) = self.camera_space_ray(s, t, r)?;
+                (origin, direction, weight, t)
+            }
+            Some(stereo) => {
+                // eye side is -1 for the left eye, 1 for the right one
+                let (side, s, t) = match stereo.layout {
+                    StereoLayout::SideBySide if s < 0.5 => (-1.0, 2.0 * s, t),
+                    StereoLayout::SideBySide => (1.0, 2.0 * s - 1.0, t),
+                    StereoLayout::TopBottom if t >= 0.5 => (-1.0, s, 2.0 * t - 1.0),
+                    StereoLayout::TopBottom => (1.0, s, 2.0 * t),
+                };
+                let (origin, direction, weight) = self.camera_space_ray(s, t, r)?;
+                let (origin, direction) = self.eye_ray(stereo, side, origin, direction);
+                (origin, direction, weight, t)
+            }
+        };
+        let time = self.shutter.sample_time(t, r);
+        let camera_to_world = self.pose.at(time).matrix();
+        Some(CameraSample {
+            ray: Ray::new(
+                Point(camera_to_world.transform_point(&origin)),
+                Point(camera_to_world.transform_vector(&direction)),
+                time,
             ),
-            r.random_double_in(self.time_start, self.time_end),
+            weight: weight.scalar_mul(self.exposure),
+        })
+    }
+
+    // moves a camera space ray to one eye, keeping the point at the
+    // convergence distance in place
+    fn eye_ray(&self, stereo: &Stereo, side: f32, origin: Vec3, direction: Vec3) -> (Vec3, Vec3) {
+        let right = match self.projection {
+            Projection::Equirectangular | Projection::CubeMap => {
+                let horizontal = Vec3::new(direction.x, 0.0, direction.z);
+                if horizontal.is_near_zero() {
+                    // straight up or down the eyes collapse on the centre
+                    Vec3::iso(0.0)
+                } else {
+                    let horizontal = horizontal.unit_norm();
+                    Vec3::new(-horizontal.z, 0.0, horizontal.x)
+                }
+            }
+            _ => Vec3::new(1.0, 0.0, 0.0),
+        };
+        let eye = right.scalar_mul(side * stereo.interocular_distance / 2.0);
+        // distance the unshifted direction covers towards the convergence
+        // point: perspective converges on a plane, the others on a sphere
+        let reach = match self.projection {
+            Projection::Perspective { .. } => -direction.z,
+            Projection::Orthographic { .. } => 0.0,
+            _ => direction.length(),
+        };
+        let direction = &direction - &eye.scalar_mul(reach / stereo.convergence_distance);
+        (origin + eye, direction)
+    }
+
+    // perspective image point at unit distance in front of the lens seen at
+    // `(s, t)`, where the lens distortion moved it from
+    fn image_plane(&self, s: f32, t: f32, magnification: f32) -> (f32, f32) {
+        let x = (s - 0.5) * self.viewport_width / magnification;
+        let y = (t - 0.5) * self.viewport_height / magnification;
+        if self.lens.distortion.is_none() {
+            (x, y)
+        } else {
+            self.lens.distortion.undistort(x, y)
+        }
+    }
+
+    fn camera_space_ray(&self, s: f32, t: f32, r: &mut Random) -> Option<(Vec3, Vec3, Color)> {
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let white = Color::new_rgb(1.0, 1.0, 1.0);
+        match self.projection {
+            Projection::Perspective { .. } => {
+                let (magnification, weight) = if self.lens.chromatic_aberration != 0.0 {
+                    // trace a single channel, each at its own magnification
+                    let channel = ((r.random_double() * 3.0) as usize).min(2);
+                    let mut rgb = [0.0; 3];
+                    rgb[channel] = 3.0;
+                    (
+                        1.0 + self.lens.chromatic_aberration * (channel as f32 - 1.0) / 2.0,
+                        Color::new_rgb(rgb[0], rgb[1], rgb[2]),
+                    )
+                } else {
+                    (1.0, white)
+                };
+                let (x, y) = self.image_plane(s, t, magnification);
+                let lens = self.lens.aperture.sample(r);
+                if self.lens.cat_eye != 0.0 {
+                    let half_height = self.viewport_height / 2.0;
+                    let barrel = Vec3::new(x / half_height, y / half_height, 0.0)
+                        .scalar_mul(self.lens.cat_eye);
+                    if (&lens - &barrel).length_squared() > 1.0 {
+                        return None;
+                    }
+                }
+                let lens = lens.scalar_mul(self.lens_radius);
+                let target = Vec3::new(x * self.focus_dist, y * self.focus_dist, -self.focus_dist);
+                let direction = &target - &lens;
+                Some((lens, direction, weight))
+            }
+            Projection::Orthographic { height } => {
+                let origin = Vec3::new(
+                    (s - 0.5) * height * self.aspect_ratio,
+                    (t - 0.5) * height,
+                    0.0,
+                );
+                Some((origin, forward, white))
+            }
+            Projection::Fisheye { fov } => {
+                let x = (2.0 * s - 1.0) * self.aspect_ratio;
+                let y = 2.0 * t - 1.0;
+                let rho = (x * x + y * y).sqrt();
+                if rho > 1.0 {
+                    return None;
+                }
+                let theta = rho * degrees_to_radians(fov) / 2.0;
+                let phi = y.atan2(x);
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                );
+                Some((Vec3::iso(0.0), direction, white))
+            }
+            Projection::Equirectangular => {
+                let longitude = (s - 0.5) * 2.0 * PI;
+                let latitude = (t - 0.5) * PI;
+                let direction = Vec3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                );
+                Some((Vec3::iso(0.0), direction, white))
+            }
+            Projection::CubeMap => {
+                let column = ((s * 3.0) as usize).min(2);
+                let row = ((t * 2.0) as usize).min(1);
+                // face coordinates, right and up in [-1, 1]
+                let a = 2.0 * (s * 3.0 - column as f32) - 1.0;
+                let b = 2.0 * (t * 2.0 - row as f32) - 1.0;
+                let direction = match (row, column) {
+                    (1, 0) => Vec3::new(-1.0, b, -a),
+                    (1, 1) => Vec3::new(a, b, -1.0),
+                    (1, _) => Vec3::new(1.0, b, a),
+                    (_, 0) => Vec3::new(-a, b, 1.0),
+                    (_, 1) => Vec3::new(a, 1.0, b),
+                    _ => Vec3::new(a, -1.0, -b),
+                };
+                Some((Vec3::iso(0.0), direction, white))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracing::material::Material;
+    use crate::ray_tracing::object::Object;
+
+    fn look_from() -> Vec3 {
+        Vec3::new(1.0, 2.0, 3.0)
+    }
+
+    fn forward() -> Vec3 {
+        Vec3::new(3.0, 0.0, -4.0).unit_norm()
+    }
+
+    fn camera() -> Camera {
+        Camera::new(
+            Point(look_from()),
+            Point(&look_from() + &forward()),
+            Point(Vec3::new(0.0, 1.0, 0.0)),
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+            0.0,
+            1.0,
         )
     }
+
+    #[test]
+    fn test_physical_exposure() {
+        // sunny 16: f/16 at 1/100s and ISO 100
+        let physical = PhysicalCamera {
+            f_number: 16.0,
+            shutter_speed: 0.01,
+            iso: 100.0,
+            ..PhysicalCamera::default()
+        };
+        assert!((physical.ev100() - 25600f32.log2()).abs() < 1e-4);
+        assert!((physical.ev100() - 14.64).abs() < 0.01);
+        let expected = 1.0 / (1.2 * 25600.0);
+        assert!((physical.exposure() / expected - 1.0).abs() < 1e-4);
+        // doubling the sensitivity is one stop less
+        let faster = PhysicalCamera {
+            iso: 200.0,
+            ..physical.clone()
+        };
+        assert!((physical.ev100() - faster.ev100() - 1.0).abs() < 1e-4);
+        // the sensor shapes the image
+        let camera = camera().with_physical(&physical);
+        assert_eq!(camera.aspect_ratio, 1.5);
+        assert!((camera.viewport_width / camera.viewport_height - 1.5).abs() < 1e-5);
+        let horizontal_fov = 2.0 * (camera.viewport_width / 2.0).atan().to_degrees();
+        let expected = 2.0 * (18.0f32 / 50.0).atan().to_degrees();
+        assert!((horizontal_fov - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_autofocus() {
+        let mut world = HittableList::new();
+        world.add(Object::Sphere {
+            center: Point(&look_from() + &forward().scalar_mul(6.0)),
+            radius: 1.0,
+            material: Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5)),
+            moving_component: None,
+        });
+        let focused = camera().with_autofocus(&world, 0.5, 0.5);
+        assert!(
+            (focused.focus_dist - 5.0).abs() < 1e-4,
+            "{}",
+            focused.focus_dist
+        );
+        // nothing in the corner
+        let unchanged = camera().with_autofocus(&world, 0.0, 0.0);
+        assert_eq!(unchanged.focus_dist, 1.0);
+        // where the distorted image shows the sphere
+        let lens = Lens {
+            distortion: Distortion {
+                k1: -0.3,
+                ..Distortion::default()
+            },
+            ..Lens::default()
+        };
+        let (s, t) = {
+            let (x, y) = lens.distortion.distort(0.8, 0.0);
+            (0.5 + x / 2.0, 0.5 + y / 2.0)
+        };
+        let mut world = HittableList::new();
+        let sideways = Vec3::new(4.0, 0.0, 3.0).unit_norm();
+        let target = &forward() + &sideways.scalar_mul(0.8);
+        world.add(Object::Sphere {
+            center: Point(&look_from() + &target.scalar_mul(4.0)),
+            radius: 0.2,
+            material: Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5)),
+            moving_component: None,
+        });
+        let focused = camera().with_lens(lens).with_autofocus(&world, s, t);
+        assert!(
+            (focused.focus_dist - 3.8).abs() < 0.05,
+            "{}",
+            focused.focus_dist
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_autofocus_needs_perspective() {
+        let world = HittableList::new();
+        let _ = camera()
+            .with_projection(Projection::Equirectangular)
+            .with_autofocus(&world, 0.5, 0.5);
+    }
+
+    #[test]
+    fn test_custom_shutter_curve() {
+        // closed in the middle third, three times as open at the end
+        let curve = ShutterCurve::custom(&[1.0, 0.0, 3.0]);
+        let mut r = Random::seeded(1);
+        let mut thirds = [0; 3];
+        let n = 40_000;
+        for _ in 0..n {
+            let x = curve.sample(&mut r);
+            assert!((0.0..=1.0).contains(&x));
+            thirds[((x * 3.0) as usize).min(2)] += 1;
+        }
+        let fractions = thirds.map(|count| count as f32 / n as f32);
+        assert!((fractions[0] - 0.25).abs() < 0.01, "{:?}", fractions);
+        assert_eq!(thirds[1], 0);
+        assert!((fractions[2] - 0.75).abs() < 0.01, "{:?}", fractions);
+        // a rolling shutter opens the top row first
+        let shutter = Shutter {
+            curve,
+            readout_time: 0.5,
+            ..Shutter::new(0.0, 1.0)
+        };
+        for _ in 0..100 {
+            let top = shutter.sample_time(1.0, &mut r);
+            assert!((0.0..=0.5).contains(&top), "{}", top);
+            let bottom = shutter.sample_time(0.0, &mut r);
+            assert!((0.5..=1.0).contains(&bottom), "{}", bottom);
+        }
+    }
+
+    fn direction_at(camera: &Camera, s: f32, t: f32) -> Vec3 {
+        let mut r = Random::seeded(1);
+        camera
+            .ray(s, t, &mut r)
+            .unwrap()
+            .ray
+            .direction
+            .0
+            .unit_norm()
+    }
+
+    #[test]
+    fn test_panoramas_look_forward() {
+        let equirectangular = camera().with_projection(Projection::Equirectangular);
+        let direction = direction_at(&equirectangular, 0.5, 0.5);
+        assert!((&direction - &forward()).length() < 1e-5);
+        // a quarter turn right, and straight up at the top
+        let right = Vec3::new(4.0, 0.0, 3.0).unit_norm();
+        assert!((&direction_at(&equirectangular, 0.75, 0.5) - &right).length() < 1e-5);
+        let up = direction_at(&equirectangular, 0.3, 1.0);
+        assert!((&up - &Vec3::new(0.0, 1.0, 0.0)).length() < 1e-5);
+
+        // the forward face is in the middle of the top row
+        let cube_map = camera().with_projection(Projection::CubeMap);
+        let direction = direction_at(&cube_map, 0.5, 0.75);
+        assert!((&direction - &forward()).length() < 1e-5);
+        assert!((&direction_at(&cube_map, 5.0 / 6.0, 0.75) - &right).length() < 1e-5);
+        assert!((&direction_at(&cube_map, 0.5, 0.25) - &Vec3::new(0.0, 1.0, 0.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_stereo_layouts() {
+        let right = Vec3::new(4.0, 0.0, 3.0).unit_norm();
+        let converge = &look_from() + &forward().scalar_mul(2.0);
+        let mut r = Random::seeded(1);
+        for (layout, left_eye, right_eye) in [
+            (StereoLayout::SideBySide, (0.25, 0.5), (0.75, 0.5)),
+            (StereoLayout::TopBottom, (0.5, 0.75), (0.5, 0.25)),
+        ] {
+            let camera = camera().with_stereo(Stereo {
+                interocular_distance: 0.1,
+                convergence_distance: 2.0,
+                layout,
+            });
+            for ((s, t), side) in [(left_eye, -1.0), (right_eye, 1.0)] {
+                let ray = camera.ray(s, t, &mut r).unwrap().ray;
+                let eye = &look_from() + &right.scalar_mul(0.05 * side);
+                assert!((&ray.origin.0 - &eye).length() < 1e-5);
+                // the centre of each half sees the convergence point
+                let to_target = (&converge - &ray.origin.0).unit_norm();
+                assert!((&ray.direction.0.unit_norm() - &to_target).length() < 1e-5);
+            }
+        }
+    }
 }
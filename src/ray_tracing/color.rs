@@ -1,9 +1,10 @@
 use std::io::Write;
 use std::iter::Sum;
-use std::ops::Add;
+use std::ops::{Add, AddAssign, Mul};
 
 use super::geom::*;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Color {
     pub rgb: Vec3,
 }
@@ -18,6 +19,15 @@ impl Color {
     pub fn zero() -> Color {
         Color::new(Vec3::iso(0.0))
     }
+    pub fn scalar_mul(&self, s: f32) -> Color {
+        Color::new(self.rgb.scalar_mul(s))
+    }
+    pub fn max_component(&self) -> f32 {
+        self.rgb.x.max(self.rgb.y).max(self.rgb.z)
+    }
+    pub fn is_black(&self) -> bool {
+        self.rgb.x <= 0.0 && self.rgb.y <= 0.0 && self.rgb.z <= 0.0
+    }
     pub fn write<W>(&self, w: &mut W, scale: f32) -> std::io::Result<()>
     where
         W: Write,
@@ -61,6 +71,28 @@ impl Add for Color {
     }
 }
 
+impl AddAssign for Color {
+    fn add_assign(&mut self, w: Color) {
+        self.rgb += w.rgb;
+    }
+}
+
+impl Mul for &Color {
+    type Output = Color;
+
+    fn mul(self, w: &Color) -> Color {
+        Color::new(self.rgb.index_wise_mul(&w.rgb))
+    }
+}
+
+impl Mul for Color {
+    type Output = Color;
+
+    fn mul(self, w: Color) -> Color {
+        &self * &w
+    }
+}
+
 impl Sum for Color {
     fn sum<I>(iter: I) -> Color
     where
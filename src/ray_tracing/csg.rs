@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use super::geom::*;
+use super::object::*;
+use super::ray::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// Points of `a` outside of `b`.
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_a || in_b,
+            CsgOperation::Intersection => in_a && in_b,
+            CsgOperation::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// Boolean combination of two closed objects.
+///
+/// All the crossings of each child along the ray are collected by hitting
+/// it repeatedly, and the surface is where the combined inside changes.
+/// Hits keep the material of the child they are on, surfaces of `b`
+/// bounding a difference face into the hole.
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub a: Arc<Object>,
+    pub b: Arc<Object>,
+}
+
+impl Csg {
+    // gap between successive hits of a child
+    const EPSILON: f32 = 1e-4;
+    // crossings of a child followed along a ray
+    const MAX_CROSSINGS: usize = 64;
+
+    pub fn new(operation: CsgOperation, a: Arc<Object>, b: Arc<Object>) -> Csg {
+        Csg { operation, a, b }
+    }
+
+    // every surface of `object` after `t_min` in order, and whether the ray
+    // starts inside of it
+    fn crossings<'a>(object: &'a Object, ray: &Ray, t_min: f32) -> (Vec<HitRecord<'a>>, bool) {
+        let mut crossings = Vec::new();
+        let mut t = t_min;
+        while crossings.len() < Csg::MAX_CROSSINGS {
+            match object.hit(ray, t, INFINITY) {
+                Some(rec) => {
+                    t = rec.t + Csg::EPSILON;
+                    crossings.push(rec);
+                }
+                None => break,
+            }
+        }
+        // closed objects are left on back faces
+        let inside = crossings.first().is_some_and(|rec| !rec.front_face);
+        (crossings, inside)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // whole rays, the inside at `t_max` depends on what comes after
+        let (hits_a, mut in_a) = Csg::crossings(&self.a, ray, t_min);
+        let (hits_b, mut in_b) = Csg::crossings(&self.b, ray, t_min);
+        let mut inside = self.operation.inside(in_a, in_b);
+        let (mut a, mut b) = (hits_a.into_iter().peekable(), hits_b.into_iter().peekable());
+        loop {
+            let from_a = match (a.peek(), b.peek()) {
+                (Some(rec_a), Some(rec_b)) => rec_a.t <= rec_b.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let mut rec = if from_a {
+                let rec = a.next()?;
+                in_a = rec.front_face;
+                rec
+            } else {
+                let rec = b.next()?;
+                in_b = rec.front_face;
+                rec
+            };
+            if rec.t > t_max {
+                return None;
+            }
+            let now_inside = self.operation.inside(in_a, in_b);
+            if now_inside != inside {
+                // the normal already faces the ray, only the side changes
+                rec.front_face = now_inside;
+                return Some(rec);
+            }
+            inside = now_inside;
+        }
+    }
+
+    fn bounding_box(&self, time_0: f32, time_1: f32) -> Option<Aabb> {
+        let a = self.a.bounding_box(time_0, time_1);
+        match self.operation {
+            CsgOperation::Union => {
+                let b = self.b.bounding_box(time_0, time_1);
+                Some(a?.surrounding(&b?))
+            }
+            // never larger than `a`
+            CsgOperation::Intersection | CsgOperation::Difference => a,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracing::color::Color;
+    use crate::ray_tracing::material::Material;
+
+    fn sphere(x: f32, radius: f32) -> Arc<Object> {
+        Arc::new(Object::Sphere {
+            center: Point(Vec3::new(x, 0.0, 0.0)),
+            radius,
+            material: Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5)),
+            moving_component: None,
+        })
+    }
+
+    #[test]
+    fn test_difference_intervals() {
+        // a unit sphere with a hole bored through by a smaller one on the
+        // right: along +x the surfaces are at -1 and 0.5
+        let csg = Csg::new(CsgOperation::Difference, sphere(0.0, 1.0), sphere(1.0, 0.5));
+        let ray = Ray::new(
+            Point(Vec3::new(-3.0, 0.0, 0.0)),
+            Point(Vec3::new(1.0, 0.0, 0.0)),
+            0.0,
+        );
+        let entry = csg.hit(&ray, 0.001, INFINITY).unwrap();
+        assert!((entry.t - 2.0).abs() < 1e-4);
+        assert!(entry.front_face);
+        let exit = csg.hit(&ray, entry.t + 0.001, INFINITY).unwrap();
+        assert!((exit.t - 3.5).abs() < 1e-4);
+        // leaving through the hole, facing into it
+        assert!(!exit.front_face);
+        assert!((&exit.normal.0 - &Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-4);
+        assert!(csg.hit(&ray, exit.t + 0.001, INFINITY).is_none());
+        // the lens shared by both starts at the left of the small one
+        let lens = Csg::new(
+            CsgOperation::Intersection,
+            sphere(0.0, 1.0),
+            sphere(1.0, 0.5),
+        );
+        let rec = lens.hit(&ray, 0.001, INFINITY).unwrap();
+        assert!((rec.t - 3.5).abs() < 1e-4 && rec.front_face);
+    }
+}
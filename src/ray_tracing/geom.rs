@@ -2,7 +2,7 @@ use super::rand::Random;
 
 use std::ops::*;
 
-pub const PI: f32 = 3.141_592_653_589_793;
+pub const PI: f32 = std::f32::consts::PI;
 
 pub const INFINITY: f32 = f32::INFINITY;
 
@@ -62,6 +62,30 @@ impl Vec3 {
     pub fn random_unit_vector(r: &mut Random) -> Vec3 {
         Vec3::random_in_unit_sphere(r).unit_norm()
     }
+
+    /// Cosine weighted direction in the hemisphere around +z, its density
+    /// is `cosine_hemisphere_pdf(z)`.
+    pub fn random_cosine_direction(r: &mut Random) -> Vec3 {
+        let phi = 2.0 * PI * r.random_double();
+        let r2 = r.random_double();
+        let sin_theta = r2.sqrt();
+        Vec3::new(
+            phi.cos() * sin_theta,
+            phi.sin() * sin_theta,
+            (1.0 - r2).sqrt(),
+        )
+    }
+
+    /// Uniform direction inside the cone subtended by a sphere of `radius`
+    /// at `distance_squared`, expressed around the +z axis.
+    pub fn random_to_sphere(r: &mut Random, radius: f32, distance_squared: f32) -> Vec3 {
+        let r1 = r.random_double();
+        let r2 = r.random_double();
+        let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).max(0.0).sqrt() - 1.0);
+        let phi = 2.0 * PI * r1;
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+    }
     const NEAR_ZERO: f32 = 1e-8;
 
     pub fn is_near_zero(&self) -> bool {
@@ -77,6 +101,22 @@ impl Vec3 {
     pub fn as_slice(&self) -> [f32; 3] {
         [self.x, self.y, self.z]
     }
+
+    pub fn axis(&self, axis: usize) -> f32 {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    pub fn min(&self, w: &Vec3) -> Vec3 {
+        Vec3::new(self.x.min(w.x), self.y.min(w.y), self.z.min(w.z))
+    }
+
+    pub fn max(&self, w: &Vec3) -> Vec3 {
+        Vec3::new(self.x.max(w.x), self.y.max(w.y), self.z.max(w.z))
+    }
     pub fn length(&self) -> f32 {
         self.length_squared().sqrt()
     }
@@ -231,6 +271,454 @@ impl Deref for Point {
     }
 }
 
+/// Axis aligned bounding box, used by the acceleration structures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn new(min: Point, max: Point) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point(self.min.0.min(&other.min.0)),
+            Point(self.max.0.max(&other.max.0)),
+        )
+    }
+
+    /// Grows the thin sides to at least `delta`, so that planar surfaces
+    /// still have a box the slab test can hit.
+    pub fn pad(&self, delta: f32) -> Aabb {
+        let grow = |min: f32, max: f32| {
+            if max - min < delta {
+                (min - delta / 2.0, max + delta / 2.0)
+            } else {
+                (min, max)
+            }
+        };
+        let (x0, x1) = grow(self.min.x, self.max.x);
+        let (y0, y1) = grow(self.min.y, self.max.y);
+        let (z0, z1) = grow(self.min.z, self.max.z);
+        Aabb::new(Point(Vec3::new(x0, y0, z0)), Point(Vec3::new(x1, y1, z1)))
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (&self.min.0 + &self.max.0).scalar_mul(0.5)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let extent = &self.max.0 - &self.min.0;
+        if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn hit(&self, origin: &Vec3, direction: &Vec3, t_min: f32, t_max: f32) -> bool {
+        self.overlap(origin, direction, t_min, t_max).is_some()
+    }
+
+    /// Part of `[t_min, t_max]` along the ray inside the box, slab test.
+    pub fn overlap(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+        mut t_min: f32,
+        mut t_max: f32,
+    ) -> Option<(f32, f32)> {
+        for axis in 0..3 {
+            let inv_d = 1.0 / direction.axis(axis);
+            let mut t0 = (self.min.axis(axis) - origin.axis(axis)) * inv_d;
+            let mut t1 = (self.max.axis(axis) - origin.axis(axis)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // widen the far side by a few ulps, so boxes of planar surfaces
+            // are not lost to rounding
+            t1 *= 1.0 + 6.0 * f32::EPSILON;
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
+
+/// Orthonormal basis built around a single direction `w`.
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(n: &Vec3) -> Onb {
+        let w = n.unit_norm();
+        let a = if w.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(&a).unit_norm();
+        let u = w.cross(&v);
+        Onb { u, v, w }
+    }
+
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        self.u.scalar_mul(a.x) + self.v.scalar_mul(a.y) + self.w.scalar_mul(a.z)
+    }
+
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.0) / PI
+}
+
+/// Row major 4x4 matrix for affine transforms of points and directions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mat4(pub [[f32; 4]; 4]);
+
+impl Mat4 {
+    pub const fn identity() -> Mat4 {
+        Mat4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: &Vec3) -> Mat4 {
+        let mut m = Mat4::identity();
+        m.0[0][3] = offset.x;
+        m.0[1][3] = offset.y;
+        m.0[2][3] = offset.z;
+        m
+    }
+
+    pub fn scaling(factors: &Vec3) -> Mat4 {
+        let mut m = Mat4::identity();
+        m.0[0][0] = factors.x;
+        m.0[1][1] = factors.y;
+        m.0[2][2] = factors.z;
+        m
+    }
+
+    /// Rotation of `degrees` around `axis`, counter clockwise looking down
+    /// the axis.
+    pub fn rotation(axis: &Vec3, degrees: f32) -> Mat4 {
+        let a = axis.unit_norm();
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        let t = 1.0 - cos;
+        Mat4([
+            [
+                t * a.x * a.x + cos,
+                t * a.x * a.y - sin * a.z,
+                t * a.x * a.z + sin * a.y,
+                0.0,
+            ],
+            [
+                t * a.x * a.y + sin * a.z,
+                t * a.y * a.y + cos,
+                t * a.y * a.z - sin * a.x,
+                0.0,
+            ],
+            [
+                t * a.x * a.z - sin * a.y,
+                t * a.y * a.z + sin * a.x,
+                t * a.z * a.z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation_x(degrees: f32) -> Mat4 {
+        Mat4::rotation(&Vec3::new(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotation_y(degrees: f32) -> Mat4 {
+        Mat4::rotation(&Vec3::new(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotation_z(degrees: f32) -> Mat4 {
+        Mat4::rotation(&Vec3::new(0.0, 0.0, 1.0), degrees)
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = Mat4::identity();
+        for (i, row) in self.0.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                m.0[j][i] = *value;
+            }
+        }
+        m
+    }
+
+    /// General inverse by cofactors, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let m = &self.0;
+        let s0 = m[0][0] * m[1][1] - m[1][0] * m[0][1];
+        let s1 = m[0][0] * m[1][2] - m[1][0] * m[0][2];
+        let s2 = m[0][0] * m[1][3] - m[1][0] * m[0][3];
+        let s3 = m[0][1] * m[1][2] - m[1][1] * m[0][2];
+        let s4 = m[0][1] * m[1][3] - m[1][1] * m[0][3];
+        let s5 = m[0][2] * m[1][3] - m[1][2] * m[0][3];
+        let c5 = m[2][2] * m[3][3] - m[3][2] * m[2][3];
+        let c4 = m[2][1] * m[3][3] - m[3][1] * m[2][3];
+        let c3 = m[2][1] * m[3][2] - m[3][1] * m[2][2];
+        let c2 = m[2][0] * m[3][3] - m[3][0] * m[2][3];
+        let c1 = m[2][0] * m[3][2] - m[3][0] * m[2][2];
+        let c0 = m[2][0] * m[3][1] - m[3][0] * m[2][1];
+        let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv = 1.0 / det;
+        Some(Mat4([
+            [
+                (m[1][1] * c5 - m[1][2] * c4 + m[1][3] * c3) * inv,
+                (-m[0][1] * c5 + m[0][2] * c4 - m[0][3] * c3) * inv,
+                (m[3][1] * s5 - m[3][2] * s4 + m[3][3] * s3) * inv,
+                (-m[2][1] * s5 + m[2][2] * s4 - m[2][3] * s3) * inv,
+            ],
+            [
+                (-m[1][0] * c5 + m[1][2] * c2 - m[1][3] * c1) * inv,
+                (m[0][0] * c5 - m[0][2] * c2 + m[0][3] * c1) * inv,
+                (-m[3][0] * s5 + m[3][2] * s2 - m[3][3] * s1) * inv,
+                (m[2][0] * s5 - m[2][2] * s2 + m[2][3] * s1) * inv,
+            ],
+            [
+                (m[1][0] * c4 - m[1][1] * c2 + m[1][3] * c0) * inv,
+                (-m[0][0] * c4 + m[0][1] * c2 - m[0][3] * c0) * inv,
+                (m[3][0] * s4 - m[3][1] * s2 + m[3][3] * s0) * inv,
+                (-m[2][0] * s4 + m[2][1] * s2 - m[2][3] * s0) * inv,
+            ],
+            [
+                (-m[1][0] * c3 + m[1][1] * c1 - m[1][2] * c0) * inv,
+                (m[0][0] * c3 - m[0][1] * c1 + m[0][2] * c0) * inv,
+                (-m[3][0] * s3 + m[3][1] * s1 - m[3][2] * s0) * inv,
+                (m[2][0] * s3 - m[2][1] * s1 + m[2][2] * s0) * inv,
+            ],
+        ]))
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Transforms a normal, `self` being the inverse of the matrix applied
+    /// to points.
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    /// Box enclosing the transformed corners of `bbox`.
+    pub fn transform_aabb(&self, bbox: &Aabb) -> Aabb {
+        let mut corners = (0..8).map(|i| {
+            let pick = |bit: usize, axis: usize| {
+                if i & bit == 0 {
+                    bbox.min.axis(axis)
+                } else {
+                    bbox.max.axis(axis)
+                }
+            };
+            self.transform_point(&Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2)))
+        });
+        let first = corners.next().unwrap_or_else(|| Vec3::iso(0.0));
+        let (min, max) = corners.fold((first.clone(), first), |(min, max), c| {
+            (min.min(&c), max.max(&c))
+        });
+        Aabb::new(Point(min), Point(max))
+    }
+}
+
+impl Mul for &Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, w: &Mat4) -> Mat4 {
+        let mut m = Mat4([[0.0; 4]; 4]);
+        for i in 0..4 {
+            for j in 0..4 {
+                m.0[i][j] = (0..4).map(|k| self.0[i][k] * w.0[k][j]).sum();
+            }
+        }
+        m
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, w: Mat4) -> Mat4 {
+        &self * &w
+    }
+}
+
+/// Unit quaternion used for rotations that need to be interpolated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quat {
+    pub w: f32,
+    pub v: Vec3,
+}
+
+impl Quat {
+    pub const fn identity() -> Quat {
+        Quat {
+            w: 1.0,
+            v: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Rotation of `degrees` around `axis`, same convention as
+    /// [`Mat4::rotation`].
+    pub fn from_axis_angle(axis: &Vec3, degrees: f32) -> Quat {
+        let (sin, cos) = (degrees_to_radians(degrees) / 2.0).sin_cos();
+        Quat {
+            w: cos,
+            v: axis.unit_norm().scalar_mul(sin),
+        }
+    }
+
+    /// Quaternion of the rotation part of `m`, which must be orthonormal.
+    pub fn from_mat4(m: &Mat4) -> Quat {
+        let m = &m.0;
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quat {
+                w: 0.25 * s,
+                v: Vec3::new(
+                    (m[2][1] - m[1][2]) / s,
+                    (m[0][2] - m[2][0]) / s,
+                    (m[1][0] - m[0][1]) / s,
+                ),
+            }
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+            Quat {
+                w: (m[2][1] - m[1][2]) / s,
+                v: Vec3::new(0.25 * s, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s),
+            }
+        } else if m[1][1] > m[2][2] {
+            let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+            Quat {
+                w: (m[0][2] - m[2][0]) / s,
+                v: Vec3::new((m[0][1] + m[1][0]) / s, 0.25 * s, (m[1][2] + m[2][1]) / s),
+            }
+        } else {
+            let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+            Quat {
+                w: (m[1][0] - m[0][1]) / s,
+                v: Vec3::new((m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, 0.25 * s),
+            }
+        };
+        q.normalize()
+    }
+
+    pub fn dot(&self, q: &Quat) -> f32 {
+        self.w * q.w + self.v.dot(&q.v)
+    }
+
+    pub fn conjugate(&self) -> Quat {
+        Quat {
+            w: self.w,
+            v: -&self.v,
+        }
+    }
+
+    pub fn normalize(&self) -> Quat {
+        let len = self.dot(self).sqrt();
+        Quat {
+            w: self.w / len,
+            v: self.v.scalar_div(len),
+        }
+    }
+
+    /// Spherical interpolation along the shortest arc.
+    pub fn slerp(&self, q: &Quat, t: f32) -> Quat {
+        let mut cos_theta = self.dot(q);
+        let q = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Quat { w: -q.w, v: -&q.v }
+        } else {
+            q.clone()
+        };
+        let (a, b) = if cos_theta > 0.9995 {
+            // nearly parallel, lerp is fine and avoids dividing by ~0
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+        Quat {
+            w: a * self.w + b * q.w,
+            v: self.v.scalar_mul(a) + q.v.scalar_mul(b),
+        }
+        .normalize()
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        let (w, x, y, z) = (self.w, self.v.x, self.v.y, self.v.z);
+        Mat4([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -242,4 +730,45 @@ mod tests {
         let z = Vec3::new(0.0, 0.0, 1.0);
         assert_eq!(x.cross(&y), z);
     }
+
+    #[test]
+    fn test_aabb_hit() {
+        let aabb = Aabb::new(
+            Point(Vec3::new(-1.0, -1.0, -1.0)),
+            Point(Vec3::new(1.0, 1.0, 1.0)),
+        );
+        let origin = Vec3::new(0.0, 0.0, -5.0);
+        assert!(aabb.hit(&origin, &Vec3::new(0.0, 0.0, 1.0), 0.0, INFINITY));
+        assert!(!aabb.hit(&origin, &Vec3::new(0.0, 1.0, 0.0), 0.0, INFINITY));
+        assert!(!aabb.hit(&origin, &Vec3::new(0.0, 0.0, 1.0), 0.0, 3.0));
+    }
+
+    #[test]
+    fn test_mat4_inverse() {
+        let m = Mat4::translation(&Vec3::new(1.0, -2.0, 3.0))
+            * Mat4::rotation(&Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Mat4::scaling(&Vec3::new(2.0, 0.5, 1.0));
+        let product = &m * &m.inverse().unwrap();
+        for (i, row) in product.0.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-5);
+            }
+        }
+        let p = Vec3::new(0.5, 1.0, -1.0);
+        let back = m.inverse().unwrap().transform_point(&m.transform_point(&p));
+        assert!((&back - &p).length() < 1e-5);
+    }
+
+    #[test]
+    fn test_quat_matches_mat4() {
+        let axis = Vec3::new(0.0, 1.0, 1.0);
+        let q = Quat::identity().slerp(&Quat::from_axis_angle(&axis, 80.0), 0.5);
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        let expected = Mat4::rotation(&axis, 40.0).transform_point(&p);
+        assert!((&q.to_mat4().transform_point(&p) - &expected).length() < 1e-5);
+        let back = Quat::from_mat4(&Mat4::rotation(&axis, 200.0)).to_mat4();
+        let expected = Mat4::rotation(&axis, 200.0).transform_point(&p);
+        assert!((&back.transform_point(&p) - &expected).length() < 1e-5);
+    }
 }
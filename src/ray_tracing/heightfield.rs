@@ -0,0 +1,277 @@
+use super::geom::*;
+use super::image::Image;
+use super::material::*;
+use super::object::*;
+use super::ray::*;
+
+/// Terrain from a grid of heights, intersected cell by cell with a 2D DDA
+/// instead of being tessellated.
+///
+/// Vertex `(i, j)` is at `x = i / (nx - 1)` and `z = j / (nz - 1)` of the
+/// extent, each cell is split into two triangles, and normals are
+/// interpolated from the vertices. `u` follows `x` and `v` follows `z`,
+/// so an image made into a heightfield drapes as a texture the same way.
+pub struct Heightfield {
+    pub nx: usize,
+    pub nz: usize,
+    heights: Vec<f32>,
+    normals: Vec<Vec3>,
+    /// Corner at `(0, 0)` and height zero.
+    pub origin: Point,
+    /// Width along `x`, height of a unit value and depth along `z`.
+    pub size: Vec3,
+    pub material: Material,
+    bounds: Aabb,
+}
+
+impl Heightfield {
+    /// `heights` are row by row along `x`, for increasing `z`. Panics with
+    /// fewer than two vertices along either side.
+    pub fn new(
+        nx: usize,
+        nz: usize,
+        heights: Vec<f32>,
+        origin: Point,
+        size: Vec3,
+        material: Material,
+    ) -> Heightfield {
+        assert!(nx >= 2 && nz >= 2, "heightfields need at least one cell");
+        assert_eq!(heights.len(), nx * nz, "wrong number of heights");
+        let (cell_x, cell_z) = (size.x / (nx - 1) as f32, size.z / (nz - 1) as f32);
+        let height = |i: usize, j: usize| heights[j.min(nz - 1) * nx + i.min(nx - 1)] * size.y;
+        // central differences, one sided at the borders
+        let normals = (0..nx * nz)
+            .map(|k| {
+                let (i, j) = (k % nx, k / nx);
+                let (i0, i1) = (i.saturating_sub(1), i + 1);
+                let (j0, j1) = (j.saturating_sub(1), j + 1);
+                let slope_x =
+                    (height(i1, j) - height(i0, j)) / ((i1.min(nx - 1) - i0) as f32 * cell_x);
+                let slope_z =
+                    (height(i, j1) - height(i, j0)) / ((j1.min(nz - 1) - j0) as f32 * cell_z);
+                Vec3::new(-slope_x, 1.0, -slope_z).unit_norm()
+            })
+            .collect();
+        let (low, high) = heights.iter().fold((f32::MAX, f32::MIN), |(low, high), h| {
+            (low.min(h * size.y), high.max(h * size.y))
+        });
+        let bounds = Aabb::new(
+            Point(&origin.0 + &Vec3::new(0.0, low, 0.0)),
+            Point(&origin.0 + &Vec3::new(size.x, high, size.z)),
+        )
+        .pad(1e-4);
+        Heightfield {
+            nx,
+            nz,
+            heights,
+            normals,
+            origin,
+            size,
+            material,
+            bounds,
+        }
+    }
+
+    /// Heights from the grey levels of `image`, its top row at the far
+    /// `z` end like a texture with `v` going up.
+    pub fn from_image(image: &Image, origin: Point, size: Vec3, material: Material) -> Heightfield {
+        let (nx, nz) = (image.width, image.height);
+        let heights = (0..nx * nz)
+            .map(|k| {
+                let pixel = image.pixel(k % nx, nz - 1 - k / nx);
+                (pixel.x + pixel.y + pixel.z) / 3.0
+            })
+            .collect();
+        Heightfield::new(nx, nz, heights, origin, size, material)
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        Vec3::new(
+            self.origin.x + self.size.x * i as f32 / (self.nx - 1) as f32,
+            self.origin.y + self.size.y * self.heights[j * self.nx + i],
+            self.origin.z + self.size.z * j as f32 / (self.nz - 1) as f32,
+        )
+    }
+
+    // nearest hit on the two triangles of cell `(i, j)`
+    fn hit_cell(&self, ray: &Ray, i: usize, j: usize, t_min: f32, t_max: f32) -> Option<CellHit> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut best = None;
+        let mut closest = t_max;
+        for triangle in [
+            [corners[0], corners[2], corners[1]],
+            [corners[0], corners[3], corners[2]],
+        ] {
+            let [a, b, c] = triangle.map(|(i, j)| self.vertex(i, j));
+            if let Some((t, b1, b2)) = hit_triangle(ray, &a, &b, &c) {
+                if t >= t_min && t <= closest {
+                    closest = t;
+                    best = Some(CellHit {
+                        t,
+                        triangle,
+                        b1,
+                        b2,
+                    });
+                }
+            }
+        }
+        best
+    }
+}
+
+struct CellHit {
+    t: f32,
+    // grid indices of the vertices
+    triangle: [(usize, usize); 3],
+    // barycentric coordinates of the second and third vertex
+    b1: f32,
+    b2: f32,
+}
+
+// Möller-Trumbore, `t` and the barycentric coordinates of `b` and `c`
+fn hit_triangle(ray: &Ray, a: &Vec3, b: &Vec3, c: &Vec3) -> Option<(f32, f32, f32)> {
+    let edge_1 = b - a;
+    let edge_2 = c - a;
+    let p = ray.direction.0.cross(&edge_2);
+    let det = edge_1.dot(&p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = &ray.origin.0 - a;
+    let b1 = s.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let q = s.cross(&edge_1);
+    let b2 = ray.direction.0.dot(&q) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    Some((edge_2.dot(&q) * inv_det, b1, b2))
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) =
+            self.bounds
+                .overlap(&ray.origin.0, &ray.direction.0, t_min, t_max)?;
+        // walk the cells under the ray in grid units
+        let cells = (self.nx - 1, self.nz - 1);
+        let cell_size = (self.size.x / cells.0 as f32, self.size.z / cells.1 as f32);
+        let start = ray.at(t_enter);
+        let grid = (
+            (start.x - self.origin.x) / cell_size.0,
+            (start.z - self.origin.z) / cell_size.1,
+        );
+        let clamp = |g: f32, n: usize| (g.floor().max(0.0) as usize).min(n - 1);
+        let (mut i, mut j) = (clamp(grid.0, cells.0), clamp(grid.1, cells.1));
+        let axis = |g: f32, cell: usize, d: f32, size: f32| {
+            if d > 0.0 {
+                (1, t_enter + ((cell + 1) as f32 - g) * size / d, size / d)
+            } else if d < 0.0 {
+                (-1, t_enter + (cell as f32 - g) * size / d, -size / d)
+            } else {
+                (0, INFINITY, INFINITY)
+            }
+        };
+        let (step_x, mut next_x, delta_x) = axis(grid.0, i, ray.direction.x, cell_size.0);
+        let (step_z, mut next_z, delta_z) = axis(grid.1, j, ray.direction.z, cell_size.1);
+        let CellHit {
+            t,
+            triangle,
+            b1,
+            b2,
+        } = loop {
+            if let Some(hit) = self.hit_cell(ray, i, j, t_min, t_max) {
+                break hit;
+            }
+            let t_next = next_x.min(next_z);
+            if t_next > t_exit {
+                return None;
+            }
+            if next_x < next_z {
+                if (step_x < 0 && i == 0) || (step_x > 0 && i + 1 == cells.0) {
+                    return None;
+                }
+                i = (i as i64 + step_x) as usize;
+                next_x += delta_x;
+            } else {
+                if (step_z < 0 && j == 0) || (step_z > 0 && j + 1 == cells.1) {
+                    return None;
+                }
+                j = (j as i64 + step_z) as usize;
+                next_z += delta_z;
+            }
+        };
+        let [a, b, c] = triangle.map(|(i, j)| self.vertex(i, j));
+        let geometric = (&b - &a).cross(&(&c - &a)).unit_norm();
+        let [na, nb, nc] = triangle.map(|(i, j)| &self.normals[j * self.nx + i]);
+        let smooth =
+            (na.scalar_mul(1.0 - b1 - b2) + nb.scalar_mul(b1) + nc.scalar_mul(b2)).unit_norm();
+        let p = ray.at(t);
+        let uv = (
+            (p.x - self.origin.x) / self.size.x,
+            (p.z - self.origin.z) / self.size.z,
+        );
+        // the geometric normal decides the side, the smooth one shades
+        let mut rec = HitRecord::new(p, t, Point(geometric), uv, &self.material, ray);
+        rec.normal = Point(if rec.front_face { smooth } else { -&smooth });
+        Some(rec.with_tangent(&Vec3::new(1.0, 0.0, 0.0)))
+    }
+
+    fn bounding_box(&self, _time_0: f32, _time_1: f32) -> Option<Aabb> {
+        Some(self.bounds.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracing::color::Color;
+    use crate::ray_tracing::rand::Random;
+
+    #[test]
+    fn test_dda_matches_brute_force() {
+        let (nx, nz) = (13, 9);
+        let heights = (0..nx * nz)
+            .map(|k| ((k % nx) as f32 * 0.7).sin() * ((k / nx) as f32 * 0.5).cos())
+            .collect();
+        let field = Heightfield::new(
+            nx,
+            nz,
+            heights,
+            Point(Vec3::new(-2.0, 0.0, -1.0)),
+            Vec3::new(4.0, 0.5, 3.0),
+            Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5)),
+        );
+        let mut r = Random::seeded(1);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = Vec3::new(0.0, 2.0, 0.0) + Vec3::random_in_unit_sphere(&mut r);
+            let target = Vec3::new(
+                4.0 * r.random_double() - 2.0,
+                0.0,
+                3.0 * r.random_double() - 1.0,
+            );
+            let ray = Ray::new(Point(origin.clone()), Point(&target - &origin), 0.0);
+            let expected = (0..nx - 1)
+                .flat_map(|i| (0..nz - 1).map(move |j| (i, j)))
+                .filter_map(|(i, j)| field.hit_cell(&ray, i, j, 0.001, INFINITY))
+                .map(|hit| hit.t)
+                .fold(None, |best: Option<f32>, t| {
+                    Some(best.map_or(t, |b| b.min(t)))
+                });
+            let found = field.hit(&ray, 0.001, INFINITY).map(|rec| rec.t);
+            match (expected, found) {
+                (Some(e), Some(f)) => {
+                    assert!((e - f).abs() < 1e-4, "{} {}", e, f);
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("{:?} {:?}", expected, found),
+            }
+        }
+        assert!(hits > 1000);
+    }
+}
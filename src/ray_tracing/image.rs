@@ -0,0 +1,165 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use super::geom::*;
+
+/// Image with components in `[0, 1]`, as read from the file.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // rows from the top
+    pixels: Vec<Vec3>,
+}
+
+impl Image {
+    /// Panics if `pixels` is not `width * height` long.
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Image {
+        assert_eq!(pixels.len(), width * height, "wrong number of pixels");
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Image> {
+        Image::parse(&fs::read(path)?)
+    }
+
+    /// Reads the netpbm formats: PGM and PPM, both plain and binary.
+    pub fn parse(bytes: &[u8]) -> Result<Image> {
+        let mut reader = NetpbmReader { bytes, pos: 0 };
+        let magic = reader.token()?;
+        let (channels, binary) = match magic {
+            "P2" => (1, false),
+            "P3" => (3, false),
+            "P5" => (1, true),
+            "P6" => (3, true),
+            _ => return Err(invalid("not a PGM or PPM image")),
+        };
+        let width = reader.number()?;
+        let height = reader.number()?;
+        let max_value = reader.number()?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid("bad maximum value"));
+        }
+        if width == 0 || height == 0 {
+            return Err(invalid("empty image"));
+        }
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(channels))
+            .ok_or_else(|| invalid("image too large"))?;
+        let values: Vec<usize> = if binary {
+            // a single whitespace separates the header from the data
+            let start = reader.pos + 1;
+            let size = if max_value < 256 { 1 } else { 2 };
+            let end = count
+                .checked_mul(size)
+                .and_then(|length| length.checked_add(start))
+                .ok_or_else(|| invalid("image too large"))?;
+            let data = bytes
+                .get(start..end)
+                .ok_or_else(|| invalid("truncated image"))?;
+            data.chunks(size)
+                .map(|c| c.iter().fold(0, |acc, b| (acc << 8) | *b as usize))
+                .collect()
+        } else {
+            (0..count).map(|_| reader.number()).collect::<Result<_>>()?
+        };
+        let scale = 1.0 / max_value as f32;
+        let pixels = values
+            .chunks(channels)
+            .map(|c| match c {
+                [g] => Vec3::iso(*g as f32 * scale),
+                [r, g, b] => Vec3::new(*r as f32 * scale, *g as f32 * scale, *b as f32 * scale),
+                _ => Vec3::iso(0.0),
+            })
+            .collect();
+        Ok(Image::new(width, height, pixels))
+    }
+
+    /// Pixel in column `x` and row `y` from the top, clamped to the image.
+    pub fn pixel(&self, x: usize, y: usize) -> &Vec3 {
+        let x = x.min(self.width - 1);
+        let y = y.min(self.height - 1);
+        &self.pixels[y * self.width + x]
+    }
+
+    pub fn pixels(&self) -> &[Vec3] {
+        &self.pixels
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+struct NetpbmReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NetpbmReader<'a> {
+    // next whitespace separated token, skipping comments
+    fn token(&mut self) -> Result<&'a str> {
+        loop {
+            match self.bytes.get(self.pos) {
+                Some(b'#') => {
+                    while !matches!(self.bytes.get(self.pos), Some(b'\n') | None) {
+                        self.pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                Some(_) => break,
+                None => return Err(invalid("truncated image")),
+            }
+        }
+        let start = self.pos;
+        while matches!(self.bytes.get(self.pos), Some(b) if !b.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos]).map_err(|_| invalid("bad header"))
+    }
+
+    fn number(&mut self) -> Result<usize> {
+        self.token()?
+            .parse()
+            .map_err(|_| invalid("expected a number"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_plain_ppm() {
+        let image = Image::parse(b"P3\n# comment\n2 1\n255\n255 0 0  0 0 255\n").unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixel(0, 0), &Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(image.pixel(1, 0), &Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_parse_binary_pgm() {
+        let image = Image::parse(b"P5 1 2 255\n\xff\x00").unwrap();
+        assert_eq!(image.pixel(0, 0), &Vec3::iso(1.0));
+        assert_eq!(image.pixel(0, 1), &Vec3::iso(0.0));
+        assert!(Image::parse(b"P5 1 2 255\n\xff").is_err());
+    }
+
+    #[test]
+    fn test_reject_bad_sizes() {
+        let header: [&[u8]; 3] = [
+            b"P5 0 2 255\n",
+            b"P3 3 0 255\n",
+            b"P6 18446744073709551615 18446744073709551615 255\n\x00",
+        ];
+        for bytes in header {
+            let error = Image::parse(bytes).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use super::geom::*;
+use super::motion::*;
+use super::object::*;
+use super::rand::*;
+use super::ray::*;
+
+/// Shared geometry placed in the world through an affine transform.
+///
+/// Rays are moved into object space, so the same `Object` (or a whole BVH)
+/// can be instanced many times without being copied.
+pub struct Instance {
+    pub object: Arc<Object>,
+    /// Object to world.
+    pub transform: Mat4,
+    /// World to object.
+    pub inverse: Mat4,
+}
+
+impl Instance {
+    /// Panics if `transform` cannot be inverted.
+    pub fn new(object: Arc<Object>, transform: Mat4) -> Instance {
+        let inverse = transform
+            .inverse()
+            .expect("instance transform must be invertible");
+        Instance {
+            object,
+            transform,
+            inverse,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        hit_transformed(
+            &self.object,
+            &self.transform,
+            &self.inverse,
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self, time_0: f32, time_1: f32) -> Option<Aabb> {
+        self.object
+            .bounding_box(time_0, time_1)
+            .map(|bbox| self.transform.transform_aabb(&bbox))
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, time: f32) -> f32 {
+        pdf_value_transformed(&self.object, &self.inverse, origin, direction, time)
+    }
+
+    fn random_direction(&self, origin: &Point, time: f32, r: &mut Random) -> Option<Vec3> {
+        random_direction_transformed(
+            &self.object,
+            &self.transform,
+            &self.inverse,
+            origin,
+            time,
+            r,
+        )
+    }
+}
+
+/// Instance whose transform is keyframed, each ray sees the object where it
+/// is at `ray.time`.
+pub struct AnimatedInstance {
+    pub object: Arc<Object>,
+    pub motion: AnimatedTransform,
+}
+
+impl AnimatedInstance {
+    pub fn new(object: Arc<Object>, motion: AnimatedTransform) -> AnimatedInstance {
+        AnimatedInstance { object, motion }
+    }
+}
+
+impl Hittable for AnimatedInstance {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let keyframe = self.motion.at(ray.time);
+        hit_transformed(
+            &self.object,
+            &keyframe.matrix(),
+            &keyframe.inverse(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self, time_0: f32, time_1: f32) -> Option<Aabb> {
+        self.object
+            .bounding_box(time_0, time_1)
+            .map(|bbox| self.motion.motion_bounds(&bbox, time_0, time_1))
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, time: f32) -> f32 {
+        let keyframe = self.motion.at(time);
+        pdf_value_transformed(&self.object, &keyframe.inverse(), origin, direction, time)
+    }
+
+    fn random_direction(&self, origin: &Point, time: f32, r: &mut Random) -> Option<Vec3> {
+        let keyframe = self.motion.at(time);
+        random_direction_transformed(
+            &self.object,
+            &keyframe.matrix(),
+            &keyframe.inverse(),
+            origin,
+            time,
+            r,
+        )
+    }
+}
+
+fn hit_transformed<'a>(
+    object: &'a Object,
+    transform: &Mat4,
+    inverse: &Mat4,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord<'a>> {
+    // the direction is not normalised, so t is the same in both spaces
+    let object_ray = Ray::new(
+        Point(inverse.transform_point(&ray.origin.0)),
+        Point(inverse.transform_vector(&ray.direction.0)),
+        ray.time,
+    )
+    .with_wavelengths(ray.wavelengths);
+    let mut rec = object.hit(&object_ray, t_min, t_max)?;
+    rec.p = Point(transform.transform_point(&rec.p.0));
+    rec.normal = Point(inverse.transform_normal(&rec.normal.0).unit_norm());
+    let tangent = transform.transform_vector(&rec.tangent);
+    Some(rec.with_tangent(&tangent))
+}
+
+// solid angles are only preserved by rigid motions and uniform scales,
+// light sampling through other transforms is biased
+fn pdf_value_transformed(
+    object: &Object,
+    inverse: &Mat4,
+    origin: &Point,
+    direction: &Vec3,
+    time: f32,
+) -> f32 {
+    object.pdf_value(
+        &Point(inverse.transform_point(&origin.0)),
+        &inverse.transform_vector(direction),
+        time,
+    )
+}
+
+fn random_direction_transformed(
+    object: &Object,
+    transform: &Mat4,
+    inverse: &Mat4,
+    origin: &Point,
+    time: f32,
+    r: &mut Random,
+) -> Option<Vec3> {
+    object
+        .random_direction(&Point(inverse.transform_point(&origin.0)), time, r)
+        .map(|direction| transform.transform_vector(&direction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracing::color::Color;
+    use crate::ray_tracing::material::Material;
+
+    #[test]
+    fn test_instance_hit_matches_transformed_sphere() {
+        let sphere = Arc::new(Object::Sphere {
+            center: Point(Vec3::iso(0.0)),
+            radius: 1.0,
+            material: Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5)),
+            moving_component: None,
+        });
+        let rotation = Mat4::rotation_y(30.0);
+        let transform = &Mat4::translation(&Vec3::new(1.0, 2.0, 3.0))
+            * &(&rotation * &Mat4::scaling(&Vec3::new(2.0, 1.0, 0.5)));
+        let instance = Instance::new(sphere, transform.clone());
+
+        let points = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.6, 0.0, 0.8),
+            Vec3::new(0.48, 0.6, -0.64),
+        ];
+        for p in points.iter() {
+            // an ellipsoid normal goes as the inverse of the scale
+            let expected_p = transform.transform_point(p);
+            let expected_normal = rotation
+                .transform_vector(&Vec3::new(p.x / 2.0, p.y, p.z / 0.5))
+                .unit_norm();
+            // coming in along the normal, from 3 units away at speed 2
+            let origin = &expected_p + &expected_normal.scalar_mul(3.0);
+            let ray = Ray::new(Point(origin), Point(expected_normal.scalar_mul(-2.0)), 0.0);
+            let rec = instance.hit(&ray, 0.001, INFINITY).unwrap();
+            assert!((rec.t - 1.5).abs() < 1e-4, "{}", rec.t);
+            assert!((&rec.p.0 - &expected_p).length() < 1e-4);
+            assert!((&rec.normal.0 - &expected_normal).length() < 1e-4);
+            assert!(rec.front_face);
+        }
+    }
+}
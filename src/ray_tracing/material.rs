@@ -1,98 +1,1543 @@
+use std::ops::BitOr;
+use std::sync::Arc;
+
 use super::color::*;
 use super::geom::*;
+use super::medium::*;
+use super::microfacet::*;
 use super::rand::*;
 use super::ray::*;
+use super::spectrum::*;
+use super::texture::*;
 use Material::*;
 
+/// Kind of lobes a BSDF has, or the lobe a sample was drawn from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct BsdfFlags(u8);
+
+impl BsdfFlags {
+    pub const NONE: BsdfFlags = BsdfFlags(0);
+    pub const REFLECTION: BsdfFlags = BsdfFlags(1);
+    pub const TRANSMISSION: BsdfFlags = BsdfFlags(1 << 1);
+    pub const DIFFUSE: BsdfFlags = BsdfFlags(1 << 2);
+    pub const GLOSSY: BsdfFlags = BsdfFlags(1 << 3);
+    /// Delta lobe: `eval` and `pdf` are zero for it, the only way to find
+    /// its directions is `sample`.
+    pub const SPECULAR: BsdfFlags = BsdfFlags(1 << 4);
+    /// The direction depends on the wavelength, on spectral paths only the
+    /// hero wavelength can follow it.
+    pub const DISPERSION: BsdfFlags = BsdfFlags(1 << 5);
+    /// Scattering inside a participating medium.
+    pub const VOLUME: BsdfFlags = BsdfFlags(1 << 6);
+
+    pub fn contains(self, other: BsdfFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// True when there is no lobe that light sampling could hit.
+    pub fn is_delta(self) -> bool {
+        !self.contains(BsdfFlags::DIFFUSE)
+            && !self.contains(BsdfFlags::GLOSSY)
+            && !self.contains(BsdfFlags::VOLUME)
+    }
+}
+
+impl BitOr for BsdfFlags {
+    type Output = BsdfFlags;
+
+    fn bitor(self, other: BsdfFlags) -> BsdfFlags {
+        BsdfFlags(self.0 | other.0)
+    }
+}
+
+pub struct BsdfSample {
+    /// Unit direction of the scattered ray.
+    pub direction: Vec3,
+    /// `f * |cos| / pdf`, what the path throughput gets multiplied by.
+    pub weight: Color,
+    /// Solid angle density of `direction`, meaningless for specular samples.
+    pub pdf: f32,
+    /// Lobe the direction was sampled from.
+    pub flags: BsdfFlags,
+}
+
+/// Scattering model of a surface.
+///
+/// `wo` is the unit direction towards the viewer (opposite to the incoming
+/// ray), `wi` the unit direction light arrives from. The normal in the hit
+/// record always faces `wo`.
+pub trait Bsdf: Send + Sync {
+    fn sample(&self, wo: &Vec3, rec: &HitRecord, r: &mut Random) -> Option<BsdfSample>;
+
+    /// BSDF value times `|cos(wi, normal)|`.
+    fn eval(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> Color {
+        Color::zero()
+    }
+
+    fn pdf(&self, _wo: &Vec3, _wi: &Vec3, _rec: &HitRecord) -> f32 {
+        0.0
+    }
+
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::zero()
+    }
+
+    /// Fraction of light left after travelling `distance` inside the
+    /// object before reaching the back face hit in `rec`.
+    fn transmittance(&self, _rec: &HitRecord, _distance: f32) -> Color {
+        Color::new_rgb(1.0, 1.0, 1.0)
+    }
+
+    fn flags(&self) -> BsdfFlags;
+}
+
+#[derive(Clone)]
 pub enum Material {
     Lambertian {
         albedo: Color,
     },
+    /// Rough diffuse, `sigma` is the standard deviation of the facet slopes
+    /// in degrees. Flattens and brightens towards grazing views like clay
+    /// or the moon.
+    OrenNayar {
+        albedo: Color,
+        sigma: f32,
+    },
+    /// Thin diffuse sheet scattering to both sides, like leaves or paper
+    /// lit from behind.
+    Translucent {
+        reflectance: Color,
+        transmittance: Color,
+    },
     Metal {
         albedo: Color,
         fuzz: f32,
     },
     Dielectric {
-        refractive_index: f32,
-        attenuation: Color,
+        refractive_index: Ior,
+        /// Beer-Lambert absorption coefficient per unit length.
+        absorption: Color,
+    },
+    DiffuseLight {
+        emit: Color,
+    },
+    /// Rough metal with a GGX microfacet distribution.
+    Conductor {
+        ior: ComplexIor,
+        roughness: Ggx,
+    },
+    /// Frosted glass with a GGX microfacet distribution.
+    RoughDielectric {
+        refractive_index: Ior,
+        roughness: Ggx,
     },
+    Principled(Box<Principled>),
+    /// `a` where `mask` is 0 and `b` where it is 1, blended in between.
+    Mix {
+        a: Box<Material>,
+        b: Box<Material>,
+        mask: Texture,
+    },
+    /// Clear dielectric layer over `base`, like varnish or lacquer. Light
+    /// the coat does not reflect reaches the base, and what the base sends
+    /// back is transmitted out again by the same Fresnel split.
+    Coated {
+        base: Box<Material>,
+        ior: f32,
+        roughness: Ggx,
+    },
+    /// `base` shaded with a normal bent by a normal or bump map.
+    Perturbed {
+        base: Box<Material>,
+        perturbation: NormalPerturbation,
+    },
+    /// Invisible boundary of a closed object filled with the medium.
+    MediumInterface(Arc<Medium>),
+    /// Closed object scattering light inside, like skin, wax or marble.
+    /// Paths cross the `boundary`, usually a dielectric, and random walk
+    /// through the `interior` medium until they leave again.
+    Subsurface {
+        boundary: Box<Material>,
+        interior: Arc<Medium>,
+    },
+    /// Phase function of scattering in media, `g` is the mean cosine of
+    /// the scattering angle.
+    HenyeyGreenstein {
+        g: f32,
+    },
+    Custom(Arc<dyn Bsdf>),
 }
 impl Material {
     pub fn new_dielectric(refractive_index: f32) -> Material {
+        Dielectric {
+            refractive_index: Ior::Constant(refractive_index),
+            absorption: Color::zero(),
+        }
+    }
+
+    /// Dielectric with a refractive index varying with the wavelength, it
+    /// only disperses light with `Integrator::spectral`.
+    pub fn new_dispersive_dielectric(refractive_index: Ior) -> Material {
         Dielectric {
             refractive_index,
-            attenuation: Color::new_rgb(1.0, 1.0, 1.0),
+            absorption: Color::zero(),
+        }
+    }
+
+    /// Dielectric whose inside tints light to `color` after `distance`,
+    /// thicker parts look darker and more saturated.
+    pub fn new_absorbing_dielectric(
+        refractive_index: f32,
+        color: Color,
+        distance: f32,
+    ) -> Material {
+        let coefficient = |c: f32| -c.clamp(1e-6, 1.0).ln() / distance;
+        Dielectric {
+            refractive_index: Ior::Constant(refractive_index),
+            absorption: Color::new_rgb(
+                coefficient(color.rgb.x),
+                coefficient(color.rgb.y),
+                coefficient(color.rgb.z),
+            ),
         }
     }
     pub fn new_lambertian(albedo: Color) -> Material {
         Lambertian { albedo }
     }
 
+    pub fn new_oren_nayar(albedo: Color, sigma: f32) -> Material {
+        OrenNayar { albedo, sigma }
+    }
+
+    pub fn new_translucent(reflectance: Color, transmittance: Color) -> Material {
+        Translucent {
+            reflectance,
+            transmittance,
+        }
+    }
+
+    // Oren-Nayar factor multiplying `albedo / π`, in the shading frame
+    fn oren_nayar(sigma: f32, wo: &Vec3, wi: &Vec3) -> f32 {
+        let sigma2 = degrees_to_radians(sigma).powi(2);
+        let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+        let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+        let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x * wo.x + wi.y * wo.y) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+        let (sin_alpha, tan_beta) = if wi.z.abs() > wo.z.abs() {
+            (sin_o, sin_i / wi.z.abs())
+        } else {
+            (sin_i, sin_o / wo.z.abs())
+        };
+        a + b * cos_phi * sin_alpha * tan_beta
+    }
+
+    // probability of sampling the reflection side of a translucent sheet
+    fn translucent_reflection_probability(reflectance: &Color, transmittance: &Color) -> f32 {
+        let r = reflectance.max_component();
+        let t = transmittance.max_component();
+        if r + t > 0.0 {
+            r / (r + t)
+        } else {
+            0.5
+        }
+    }
+
     pub fn new_metal(albedo: Color, fuzz: f32) -> Material {
         Metal { albedo, fuzz }
     }
 
-    pub fn scatter<'a>(
-        &'a self,
-        ray_in: &'a Ray,
-        hit_record: &'a HitRecord,
-        r: &mut Random,
-    ) -> Option<(&'a Color, Ray<'a>)> {
+    pub fn new_diffuse_light(emit: Color) -> Material {
+        DiffuseLight { emit }
+    }
+
+    pub fn new_conductor(ior: ComplexIor, roughness: Ggx) -> Material {
+        Conductor { ior, roughness }
+    }
+
+    pub fn new_rough_dielectric(refractive_index: Ior, roughness: Ggx) -> Material {
+        RoughDielectric {
+            refractive_index,
+            roughness,
+        }
+    }
+
+    // index of the far side over the near one, and whether it depends on
+    // the wavelength of this hit
+    fn relative_ior(refractive_index: &Ior, hit_record: &HitRecord) -> (f32, BsdfFlags) {
+        let (n, dispersion) = match &hit_record.wavelengths {
+            Some(wavelengths) if refractive_index.is_dispersive() => (
+                refractive_index.at(wavelengths.hero()),
+                BsdfFlags::DISPERSION,
+            ),
+            _ => (refractive_index.at(Ior::D_LINE), BsdfFlags::NONE),
+        };
+        if hit_record.front_face {
+            (n, dispersion)
+        } else {
+            (1.0 / n, dispersion)
+        }
+    }
+
+    pub fn new_principled(principled: Principled) -> Material {
+        Principled(Box::new(principled))
+    }
+
+    pub fn new_mix(a: Material, b: Material, mask: Texture) -> Material {
+        Mix {
+            a: Box::new(a),
+            b: Box::new(b),
+            mask,
+        }
+    }
+
+    pub fn new_coated(base: Material, ior: f32, roughness: Ggx) -> Material {
+        Coated {
+            base: Box::new(base),
+            ior,
+            roughness,
+        }
+    }
+
+    fn mix_amount(mask: &Texture, hit_record: &HitRecord) -> f32 {
+        mask.scalar(hit_record.u, hit_record.v, &hit_record.p)
+            .clamp(0.0, 1.0)
+    }
+
+    // fraction of light crossing a coat with index `ior` at `cos`, from
+    // either side
+    fn coat_transmission(ior: f32, cos: f32) -> f32 {
+        if cos > 0.0 {
+            1.0 - fresnel_dielectric(cos, ior)
+        } else {
+            1.0
+        }
+    }
+
+    // combines a direction drawn from one lobe with the density and value
+    // of the whole material
+    fn mixture_sample(
+        &self,
+        wo: &Vec3,
+        direction: Vec3,
+        hit_record: &HitRecord,
+        flags: BsdfFlags,
+    ) -> Option<BsdfSample> {
+        let pdf = self.pdf(wo, &direction, hit_record);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            weight: self.eval(wo, &direction, hit_record).scalar_mul(1.0 / pdf),
+            direction,
+            pdf,
+            flags,
+        })
+    }
+
+    pub fn new_perturbed(base: Material, perturbation: NormalPerturbation) -> Material {
+        Perturbed {
+            base: Box::new(base),
+            perturbation,
+        }
+    }
+
+    // false for directions on different sides of the geometric and the
+    // shading surface, light would leak through the surface
+    fn same_side(geometric: &HitRecord, shading: &HitRecord, wi: &Vec3) -> bool {
+        (wi.dot(&geometric.normal.0) > 0.0) == (wi.dot(&shading.normal.0) > 0.0)
+    }
+
+    /// Boundary of `medium`, the object it is given to must be closed.
+    pub fn new_medium(medium: Medium) -> Material {
+        MediumInterface(Arc::new(medium))
+    }
+
+    /// Subsurface scattering under a dielectric boundary of index `ior`.
+    /// `albedo` is the colour of the object seen from far away, and light
+    /// travels `mean_free_path` between interactions inside, per channel.
+    pub fn new_subsurface(
+        albedo: Color,
+        mean_free_path: Color,
+        ior: f32,
+        roughness: f32,
+    ) -> Material {
+        // single scattering albedo giving `albedo` after multiple
+        // scattering (Chiang et al. 2016)
+        let invert = |a: f32| {
+            let a = a.clamp(0.0, 0.999);
+            let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            1.0 - s * s
+        };
+        let inverse = |d: f32| if d > 0.0 { 1.0 / d } else { 0.0 };
+        let sigma_t = Color::new_rgb(
+            inverse(mean_free_path.rgb.x),
+            inverse(mean_free_path.rgb.y),
+            inverse(mean_free_path.rgb.z),
+        );
+        let single = Color::new_rgb(
+            invert(albedo.rgb.x),
+            invert(albedo.rgb.y),
+            invert(albedo.rgb.z),
+        );
+        let sigma_s = &sigma_t * &single;
+        let sigma_a = Color::new(&sigma_t.rgb - &sigma_s.rgb);
+        Subsurface {
+            boundary: Box::new(Material::new_rough_dielectric(
+                Ior::Constant(ior),
+                Ggx::isotropic(roughness),
+            )),
+            interior: Arc::new(Medium::new(1.0, sigma_a, sigma_s, 0.0)),
+        }
+    }
+
+    /// Medium on the back side of the surface, that paths transmitted
+    /// through it travel in.
+    pub fn interior(&self) -> Option<&Medium> {
+        match self {
+            MediumInterface(medium)
+            | Subsurface {
+                interior: medium, ..
+            } => Some(medium),
+            Perturbed { base, .. } => base.interior(),
+            _ => None,
+        }
+    }
+
+    /// Whether paths under the surface random walk through its interior.
+    pub fn is_subsurface(&self) -> bool {
+        match self {
+            Subsurface { .. } => true,
+            Perturbed { base, .. } => base.is_subsurface(),
+            _ => false,
+        }
+    }
+
+    pub fn custom<B: Bsdf + 'static>(bsdf: B) -> Material {
+        Custom(Arc::new(bsdf))
+    }
+
+    pub fn reflectance(refractive_index: f32, cosine: f32) -> f32 {
+        let r0 = ((1.0 - refractive_index) / (1.0 + refractive_index)).powi(2);
+        r0 + (1.0 - r0) * ((1.0 - cosine).powi(5))
+    }
+}
+
+impl Bsdf for Material {
+    fn sample(&self, wo: &Vec3, hit_record: &HitRecord, r: &mut Random) -> Option<BsdfSample> {
         match self {
             Lambertian { albedo } => {
-                let mut scatter_direction = &hit_record.normal.0 + &Vec3::random_unit_vector(r);
-                if scatter_direction.is_near_zero() {
-                    scatter_direction = hit_record.normal.0.clone();
-                }
-                Some((
-                    &albedo,
-                    Ray::new(&hit_record.p, Point(scatter_direction), ray_in.time),
-                ))
+                let local = Vec3::random_cosine_direction(r);
+                Some(BsdfSample {
+                    direction: hit_record.shading_frame().local(&local),
+                    weight: albedo.clone(),
+                    pdf: cosine_hemisphere_pdf(local.z),
+                    flags: BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+                })
+            }
+            OrenNayar { albedo, sigma } => {
+                let frame = hit_record.shading_frame();
+                let wi = Vec3::random_cosine_direction(r);
+                let factor = Material::oren_nayar(*sigma, &frame.to_local(wo), &wi);
+                Some(BsdfSample {
+                    direction: frame.local(&wi),
+                    weight: albedo.scalar_mul(factor),
+                    pdf: cosine_hemisphere_pdf(wi.z),
+                    flags: BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+                })
+            }
+            Translucent {
+                reflectance,
+                transmittance,
+            } => {
+                let p = Material::translucent_reflection_probability(reflectance, transmittance);
+                let local = Vec3::random_cosine_direction(r);
+                let (local, weight, probability, lobe) = if r.random_double() < p {
+                    (local, reflectance, p, BsdfFlags::REFLECTION)
+                } else {
+                    let below = Vec3::new(local.x, local.y, -local.z);
+                    (below, transmittance, 1.0 - p, BsdfFlags::TRANSMISSION)
+                };
+                Some(BsdfSample {
+                    direction: hit_record.shading_frame().local(&local),
+                    weight: weight.scalar_mul(1.0 / probability),
+                    pdf: probability * cosine_hemisphere_pdf(local.z.abs()),
+                    flags: BsdfFlags::DIFFUSE | lobe,
+                })
             }
             Metal { albedo, fuzz } => {
-                let reflected = ray_in.direction.0.unit_norm().reflect(&hit_record.normal.0);
-                let ray_out = Ray::new(
-                    &hit_record.p,
-                    Point(reflected + Vec3::random_in_unit_sphere(r).scalar_mul(*fuzz)),
-                    ray_in.time,
-                );
-                if ray_out.direction.0.dot(&hit_record.normal.0) > 0.0 {
-                    Some((&albedo, ray_out))
+                let reflected = (-wo).reflect(&hit_record.normal.0);
+                let direction =
+                    (reflected + Vec3::random_in_unit_sphere(r).scalar_mul(*fuzz)).unit_norm();
+                if direction.dot(&hit_record.normal.0) > 0.0 {
+                    // fuzzy reflections have no closed form density, they are
+                    // handled like a delta lobe
+                    Some(BsdfSample {
+                        direction,
+                        weight: albedo.clone(),
+                        pdf: 1.0,
+                        flags: BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+                    })
                 } else {
                     None
                 }
             }
             Dielectric {
-                refractive_index,
-                attenuation,
+                refractive_index, ..
             } => {
-                let refractive_ratio = if hit_record.front_face {
-                    1.0 / refractive_index
-                } else {
-                    *refractive_index
-                };
-                let unit_direction = ray_in.direction.0.unit_norm();
+                let (eta, dispersion) = Material::relative_ior(refractive_index, hit_record);
+                let refractive_ratio = 1.0 / eta;
+                let unit_direction = -wo;
                 let cos_theta = (-unit_direction.dot(&hit_record.normal.0)).min(1.0);
                 let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
                 let cannot_refract = refractive_ratio * sin_theta > 1.0;
-                let ray_out = if cannot_refract
+                let (direction, lobe) = if cannot_refract
                     || Material::reflectance(refractive_ratio, cos_theta) > r.random_double()
                 {
-                    unit_direction.reflect(&hit_record.normal.0)
+                    (
+                        unit_direction.reflect(&hit_record.normal.0),
+                        BsdfFlags::REFLECTION,
+                    )
                 } else {
-                    unit_direction.refract(&hit_record.normal.0, refractive_ratio)
+                    (
+                        unit_direction.refract(&hit_record.normal.0, refractive_ratio),
+                        BsdfFlags::TRANSMISSION,
+                    )
                 };
 
-                Some((
-                    &attenuation,
-                    Ray::new(&hit_record.p, Point(ray_out), ray_in.time),
-                ))
+                Some(BsdfSample {
+                    direction,
+                    weight: Color::new_rgb(1.0, 1.0, 1.0),
+                    pdf: 1.0,
+                    flags: BsdfFlags::SPECULAR | lobe | dispersion,
+                })
+            }
+            Conductor { ior, roughness } => {
+                let frame = hit_record.shading_frame();
+                let wo_local = frame.to_local(wo);
+                if roughness.is_smooth() {
+                    let wi = Vec3::new(-wo_local.x, -wo_local.y, wo_local.z);
+                    return Some(BsdfSample {
+                        direction: frame.local(&wi),
+                        weight: ior.fresnel(wo_local.z),
+                        pdf: 1.0,
+                        flags: BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+                    });
+                }
+                let wi = roughness.sample_reflection(&wo_local, r)?;
+                let (f_cos, pdf, wm) = roughness.reflection(&wo_local, &wi)?;
+                Some(BsdfSample {
+                    direction: frame.local(&wi),
+                    weight: ior.fresnel(wo_local.dot(&wm)).scalar_mul(f_cos / pdf),
+                    pdf,
+                    flags: BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+                })
+            }
+            RoughDielectric {
+                refractive_index,
+                roughness,
+            } => {
+                let (eta, dispersion) = Material::relative_ior(refractive_index, hit_record);
+                let frame = hit_record.shading_frame();
+                let wo_local = frame.to_local(wo);
+                if roughness.is_smooth() {
+                    let normal = Vec3::new(0.0, 0.0, 1.0);
+                    let reflectance = fresnel_dielectric(wo_local.z, eta);
+                    let (wi, lobe) = match refract(&wo_local, &normal, eta) {
+                        Some(wi) if r.random_double() >= reflectance => {
+                            (wi, BsdfFlags::TRANSMISSION)
+                        }
+                        _ => (
+                            Vec3::new(-wo_local.x, -wo_local.y, wo_local.z),
+                            BsdfFlags::REFLECTION,
+                        ),
+                    };
+                    return Some(BsdfSample {
+                        direction: frame.local(&wi),
+                        weight: Color::new_rgb(1.0, 1.0, 1.0),
+                        pdf: 1.0,
+                        flags: BsdfFlags::SPECULAR | lobe | dispersion,
+                    });
+                }
+                // pick the lobe by the Fresnel term of the sampled facet
+                let wm = roughness.sample_visible(&wo_local, r);
+                let reflectance = fresnel_dielectric(wo_local.dot(&wm), eta);
+                let (wi, lobe) = match refract(&wo_local, &wm, eta) {
+                    Some(wi) if r.random_double() >= reflectance => (wi, BsdfFlags::TRANSMISSION),
+                    _ => ((-&wo_local).reflect(&wm), BsdfFlags::REFLECTION),
+                };
+                let f_cos = self.eval(wo, &frame.local(&wi), hit_record);
+                let pdf = self.pdf(wo, &frame.local(&wi), hit_record);
+                if pdf <= 0.0 {
+                    return None;
+                }
+                Some(BsdfSample {
+                    direction: frame.local(&wi),
+                    weight: f_cos.scalar_mul(1.0 / pdf),
+                    pdf,
+                    flags: BsdfFlags::GLOSSY | lobe | dispersion,
+                })
+            }
+            DiffuseLight { .. } => None,
+            Principled(principled) => principled.sample(wo, hit_record, r),
+            Mix { a, b, mask } => {
+                let chosen = if r.random_double() < Material::mix_amount(mask, hit_record) {
+                    b
+                } else {
+                    a
+                };
+                let sample = chosen.sample(wo, hit_record, r)?;
+                if sample.flags.contains(BsdfFlags::SPECULAR) {
+                    // picked with the probability it is weighted by
+                    return Some(sample);
+                }
+                self.mixture_sample(wo, sample.direction, hit_record, sample.flags)
             }
+            Coated {
+                base,
+                ior,
+                roughness,
+            } => {
+                let frame = hit_record.shading_frame();
+                let wo_local = frame.to_local(wo);
+                if r.random_double() < fresnel_dielectric(wo_local.z, *ior) {
+                    if roughness.is_smooth() {
+                        let wi = Vec3::new(-wo_local.x, -wo_local.y, wo_local.z);
+                        return Some(BsdfSample {
+                            direction: frame.local(&wi),
+                            weight: Color::new_rgb(1.0, 1.0, 1.0),
+                            pdf: 1.0,
+                            flags: BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+                        });
+                    }
+                    let wi = roughness.sample_reflection(&wo_local, r)?;
+                    return self.mixture_sample(
+                        wo,
+                        frame.local(&wi),
+                        hit_record,
+                        BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+                    );
+                }
+                let sample = base.sample(wo, hit_record, r)?;
+                if sample.flags.contains(BsdfFlags::SPECULAR) {
+                    // the entry loss is the probability of getting here
+                    let cos_i = sample.direction.dot(&hit_record.normal.0);
+                    let exit = Material::coat_transmission(*ior, cos_i);
+                    return Some(BsdfSample {
+                        weight: sample.weight.scalar_mul(exit),
+                        ..sample
+                    });
+                }
+                self.mixture_sample(wo, sample.direction, hit_record, sample.flags)
+            }
+            Perturbed { base, perturbation } => {
+                let shading = perturbation.apply(wo, hit_record);
+                let sample = base.sample(wo, &shading, r)?;
+                if Material::same_side(hit_record, &shading, &sample.direction) {
+                    Some(sample)
+                } else {
+                    None
+                }
+            }
+            Subsurface { boundary, .. } => boundary.sample(wo, hit_record, r),
+            MediumInterface(_) => Some(BsdfSample {
+                direction: -wo,
+                weight: Color::new_rgb(1.0, 1.0, 1.0),
+                pdf: 1.0,
+                flags: BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION,
+            }),
+            HenyeyGreenstein { g } => {
+                // around the direction light travels in
+                let cos_theta = sample_henyey_greenstein(*g, r);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * r.random_double();
+                let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                Some(BsdfSample {
+                    direction: Onb::from_w(&-wo).local(&local),
+                    weight: Color::new_rgb(1.0, 1.0, 1.0),
+                    pdf: henyey_greenstein(cos_theta, *g),
+                    flags: BsdfFlags::VOLUME,
+                })
+            }
+            Custom(bsdf) => bsdf.sample(wo, hit_record, r),
         }
     }
 
-    pub fn reflectance(refractive_index: f32, cosine: f32) -> f32 {
-        let r0 = ((1.0 - refractive_index) / (1.0 + refractive_index)).powi(2);
-        r0 + (1.0 - r0) * ((1.0 - cosine).powi(5))
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit_record: &HitRecord) -> Color {
+        match self {
+            Lambertian { albedo } => {
+                albedo.scalar_mul(cosine_hemisphere_pdf(wi.dot(&hit_record.normal.0)))
+            }
+            OrenNayar { albedo, sigma } => {
+                let frame = hit_record.shading_frame();
+                let wi = frame.to_local(wi);
+                if wi.z <= 0.0 {
+                    return Color::zero();
+                }
+                let factor = Material::oren_nayar(*sigma, &frame.to_local(wo), &wi);
+                albedo.scalar_mul(factor * cosine_hemisphere_pdf(wi.z))
+            }
+            Translucent {
+                reflectance,
+                transmittance,
+            } => {
+                let cos = wi.dot(&hit_record.normal.0);
+                let side = if cos >= 0.0 {
+                    reflectance
+                } else {
+                    transmittance
+                };
+                side.scalar_mul(cosine_hemisphere_pdf(cos.abs()))
+            }
+            Conductor { ior, roughness } if !roughness.is_smooth() => {
+                let frame = hit_record.shading_frame();
+                let wo = frame.to_local(wo);
+                match roughness.reflection(&wo, &frame.to_local(wi)) {
+                    Some((f_cos, _, wm)) => ior.fresnel(wo.dot(&wm)).scalar_mul(f_cos),
+                    None => Color::zero(),
+                }
+            }
+            RoughDielectric {
+                refractive_index,
+                roughness,
+            } if !roughness.is_smooth() => {
+                let (eta, _) = Material::relative_ior(refractive_index, hit_record);
+                let frame = hit_record.shading_frame();
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                let f_cos = match roughness.reflection(&wo, &wi) {
+                    Some((f_cos, _, wm)) => fresnel_dielectric(wo.dot(&wm), eta) * f_cos,
+                    None => match roughness.transmission(&wo, &wi, eta) {
+                        Some((f_cos, _, wm)) => {
+                            (1.0 - fresnel_dielectric(wo.dot(&wm), eta)) * f_cos
+                        }
+                        None => 0.0,
+                    },
+                };
+                Color::new_rgb(f_cos, f_cos, f_cos)
+            }
+            Principled(principled) => principled.eval(wo, wi, hit_record),
+            Mix { a, b, mask } => {
+                let m = Material::mix_amount(mask, hit_record);
+                a.eval(wo, wi, hit_record).scalar_mul(1.0 - m)
+                    + b.eval(wo, wi, hit_record).scalar_mul(m)
+            }
+            Coated {
+                base,
+                ior,
+                roughness,
+            } => {
+                let frame = hit_record.shading_frame();
+                let (wo_local, wi_local) = (frame.to_local(wo), frame.to_local(wi));
+                let through = Material::coat_transmission(*ior, wo_local.z)
+                    * Material::coat_transmission(*ior, wi_local.z);
+                let mut f = base.eval(wo, wi, hit_record).scalar_mul(through);
+                if !roughness.is_smooth() {
+                    if let Some((f_cos, _, wm)) = roughness.reflection(&wo_local, &wi_local) {
+                        let coat = fresnel_dielectric(wo_local.dot(&wm), *ior) * f_cos;
+                        f += Color::new(Vec3::iso(coat));
+                    }
+                }
+                f
+            }
+            Perturbed { base, perturbation } => {
+                let shading = perturbation.apply(wo, hit_record);
+                if Material::same_side(hit_record, &shading, wi) {
+                    base.eval(wo, wi, &shading)
+                } else {
+                    Color::zero()
+                }
+            }
+            Subsurface { boundary, .. } => boundary.eval(wo, wi, hit_record),
+            HenyeyGreenstein { g } => {
+                let value = henyey_greenstein(-wo.dot(wi), *g);
+                Color::new_rgb(value, value, value)
+            }
+            Custom(bsdf) => bsdf.eval(wo, wi, hit_record),
+            _ => Color::zero(),
+        }
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit_record: &HitRecord) -> f32 {
+        match self {
+            Lambertian { .. } | OrenNayar { .. } => {
+                cosine_hemisphere_pdf(wi.dot(&hit_record.normal.0))
+            }
+            Translucent {
+                reflectance,
+                transmittance,
+            } => {
+                let p = Material::translucent_reflection_probability(reflectance, transmittance);
+                let cos = wi.dot(&hit_record.normal.0);
+                let side = if cos >= 0.0 { p } else { 1.0 - p };
+                side * cosine_hemisphere_pdf(cos.abs())
+            }
+            Conductor { roughness, .. } if !roughness.is_smooth() => {
+                let frame = hit_record.shading_frame();
+                roughness
+                    .reflection(&frame.to_local(wo), &frame.to_local(wi))
+                    .map_or(0.0, |(_, pdf, _)| pdf)
+            }
+            RoughDielectric {
+                refractive_index,
+                roughness,
+            } if !roughness.is_smooth() => {
+                let (eta, _) = Material::relative_ior(refractive_index, hit_record);
+                let frame = hit_record.shading_frame();
+                let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+                match roughness.reflection(&wo, &wi) {
+                    Some((_, pdf, wm)) => fresnel_dielectric(wo.dot(&wm), eta) * pdf,
+                    None => match roughness.transmission(&wo, &wi, eta) {
+                        Some((_, pdf, wm)) => (1.0 - fresnel_dielectric(wo.dot(&wm), eta)) * pdf,
+                        None => 0.0,
+                    },
+                }
+            }
+            Principled(principled) => principled.pdf(wo, wi, hit_record),
+            Mix { a, b, mask } => {
+                let m = Material::mix_amount(mask, hit_record);
+                (1.0 - m) * a.pdf(wo, wi, hit_record) + m * b.pdf(wo, wi, hit_record)
+            }
+            Coated {
+                base,
+                ior,
+                roughness,
+            } => {
+                let frame = hit_record.shading_frame();
+                let wo_local = frame.to_local(wo);
+                let coat = fresnel_dielectric(wo_local.z, *ior);
+                let mut pdf = (1.0 - coat) * base.pdf(wo, wi, hit_record);
+                if !roughness.is_smooth() {
+                    if let Some((_, coat_pdf, _)) =
+                        roughness.reflection(&wo_local, &frame.to_local(wi))
+                    {
+                        pdf += coat * coat_pdf;
+                    }
+                }
+                pdf
+            }
+            Perturbed { base, perturbation } => {
+                let shading = perturbation.apply(wo, hit_record);
+                if Material::same_side(hit_record, &shading, wi) {
+                    base.pdf(wo, wi, &shading)
+                } else {
+                    0.0
+                }
+            }
+            Subsurface { boundary, .. } => boundary.pdf(wo, wi, hit_record),
+            HenyeyGreenstein { g } => henyey_greenstein(-wo.dot(wi), *g),
+            Custom(bsdf) => bsdf.pdf(wo, wi, hit_record),
+            _ => 0.0,
+        }
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        match self {
+            DiffuseLight { emit } if hit_record.front_face => emit.clone(),
+            Principled(principled) => principled.emitted(hit_record),
+            Mix { a, b, mask } => {
+                let m = Material::mix_amount(mask, hit_record);
+                a.emitted(hit_record).scalar_mul(1.0 - m) + b.emitted(hit_record).scalar_mul(m)
+            }
+            Coated { base, .. } | Perturbed { base, .. } => base.emitted(hit_record),
+            Custom(bsdf) => bsdf.emitted(hit_record),
+            _ => Color::zero(),
+        }
+    }
+
+    fn transmittance(&self, hit_record: &HitRecord, distance: f32) -> Color {
+        match self {
+            Dielectric { absorption, .. } if !hit_record.front_face => Color::new_rgb(
+                (-absorption.rgb.x * distance).exp(),
+                (-absorption.rgb.y * distance).exp(),
+                (-absorption.rgb.z * distance).exp(),
+            ),
+            Mix { a, b, mask } => {
+                let m = Material::mix_amount(mask, hit_record);
+                a.transmittance(hit_record, distance).scalar_mul(1.0 - m)
+                    + b.transmittance(hit_record, distance).scalar_mul(m)
+            }
+            Coated { base, .. } | Perturbed { base, .. } => {
+                base.transmittance(hit_record, distance)
+            }
+            Custom(bsdf) => bsdf.transmittance(hit_record, distance),
+            _ => Color::new_rgb(1.0, 1.0, 1.0),
+        }
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        match self {
+            Lambertian { .. } | OrenNayar { .. } => BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+            Translucent { .. } => {
+                BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+            }
+            Metal { .. } => BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+            Dielectric { .. } => {
+                BsdfFlags::SPECULAR | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+            }
+            Conductor { roughness, .. } if roughness.is_smooth() => {
+                BsdfFlags::SPECULAR | BsdfFlags::REFLECTION
+            }
+            Conductor { .. } => BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+            RoughDielectric { roughness, .. } if roughness.is_smooth() => {
+                BsdfFlags::SPECULAR | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+            }
+            RoughDielectric { .. } => {
+                BsdfFlags::GLOSSY | BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION
+            }
+            DiffuseLight { .. } => BsdfFlags::NONE,
+            Principled(principled) => principled.flags(),
+            Mix { a, b, .. } => a.flags() | b.flags(),
+            Coated {
+                base, roughness, ..
+            } => {
+                let coat = if roughness.is_smooth() {
+                    BsdfFlags::SPECULAR
+                } else {
+                    BsdfFlags::GLOSSY
+                };
+                base.flags() | coat | BsdfFlags::REFLECTION
+            }
+            Perturbed { base, .. } => base.flags(),
+            Subsurface { boundary, .. } => boundary.flags(),
+            MediumInterface(_) => BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION,
+            HenyeyGreenstein { .. } => BsdfFlags::VOLUME,
+            Custom(bsdf) => bsdf.flags(),
+        }
+    }
+}
+
+/// How a `Perturbed` material bends the shading normal.
+#[derive(Clone)]
+pub enum NormalPerturbation {
+    /// Tangent space normal map, with `strength` scaling its slopes.
+    NormalMap { texture: Texture, strength: f32 },
+    /// Height texture, `scale` is the height of a unit texture value in
+    /// units of the surface coordinates. It is differentiated along `u`
+    /// and `v`, so solid textures do not bump.
+    BumpMap { height: Texture, scale: f32 },
+}
+
+impl NormalPerturbation {
+    // finite difference step of bump maps in surface coordinates
+    const BUMP_DELTA: f32 = 1e-3;
+
+    /// Copy of `rec` with the bent normal. Normals turned away from the
+    /// viewer are dropped for the geometric one.
+    pub fn apply<'a>(&self, wo: &Vec3, rec: &HitRecord<'a>) -> HitRecord<'a> {
+        // textures are authored against the outward normal
+        let side = if rec.front_face { 1.0 } else { -1.0 };
+        let normal = rec.normal.0.scalar_mul(side);
+        let tangent = &rec.tangent;
+        let bitangent = normal.cross(tangent);
+        let bent = match self {
+            NormalPerturbation::NormalMap { texture, strength } => {
+                let c = texture.value(rec.u, rec.v, &rec.p).rgb;
+                tangent.scalar_mul((2.0 * c.x - 1.0) * strength)
+                    + bitangent.scalar_mul((2.0 * c.y - 1.0) * strength)
+                    + normal.scalar_mul(2.0 * c.z - 1.0)
+            }
+            NormalPerturbation::BumpMap { height, scale } => {
+                let delta = NormalPerturbation::BUMP_DELTA;
+                let h = height.scalar(rec.u, rec.v, &rec.p);
+                let dh_du = (height.scalar(rec.u + delta, rec.v, &rec.p) - h) / delta;
+                let dh_dv = (height.scalar(rec.u, rec.v + delta, &rec.p) - h) / delta;
+                &normal - &(tangent.scalar_mul(scale * dh_du) + bitangent.scalar_mul(scale * dh_dv))
+            }
+        };
+        let bent = bent.scalar_mul(side);
+        let mut shading = rec.clone();
+        if bent.length_squared() > 0.0 && bent.dot(wo) > 0.0 {
+            shading.normal = Point(bent.unit_norm());
+            let tangent = rec.tangent.clone();
+            shading = shading.with_tangent(&tangent);
+        }
+        shading
+    }
+}
+
+/// Disney style uber material, as exported by authoring tools. Everything
+/// is in `[0, 1]` but `ior`.
+///
+/// Diffuse with sheen, a GGX specular lobe tinted towards the base colour
+/// by `metallic`, rough glass for `transmission` and a clearcoat layer are
+/// added together, and sampled by picking a lobe from their approximate
+/// albedos.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    pub base_color: Color,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Stretches highlights along the first tangent.
+    pub anisotropic: f32,
+    /// Reflectance of the dielectric base, 0.5 is 4%.
+    pub specular: f32,
+    pub specular_tint: f32,
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub transmission: f32,
+    pub ior: f32,
+    pub emission: Color,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_color: Color::new_rgb(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            anisotropic: 0.0,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            transmission: 0.0,
+            ior: 1.45,
+            emission: Color::zero(),
+        }
+    }
+}
+
+impl Principled {
+    // keeps every lobe glossy, so that lights can be sampled
+    const MIN_ROUGHNESS: f32 = 0.05;
+
+    fn specular_distribution(&self) -> Ggx {
+        let roughness = self.roughness.max(Principled::MIN_ROUGHNESS);
+        let aspect = (1.0 - 0.9 * self.anisotropic).sqrt().sqrt();
+        Ggx::anisotropic(roughness / aspect, roughness * aspect)
+    }
+
+    fn clearcoat_distribution(&self) -> Ggx {
+        Ggx::isotropic(self.clearcoat_roughness.max(Principled::MIN_ROUGHNESS))
+    }
+
+    fn tint(&self) -> Color {
+        let luminance = luminance(&self.base_color);
+        if luminance > 0.0 {
+            self.base_color.scalar_mul(1.0 / luminance)
+        } else {
+            Color::new_rgb(1.0, 1.0, 1.0)
+        }
+    }
+
+    // normal incidence reflectance of the specular lobe
+    fn specular_f0(&self) -> Color {
+        let white = Color::new_rgb(1.0, 1.0, 1.0);
+        let dielectric =
+            lerp(&white, &self.tint(), self.specular_tint).scalar_mul(0.08 * self.specular);
+        lerp(&dielectric, &self.base_color, self.metallic)
+    }
+
+    // weights of the diffuse, specular, glass and clearcoat lobes
+    fn weights(&self) -> [f32; 4] {
+        let opaque = 1.0 - self.transmission;
+        [
+            (1.0 - self.metallic) * opaque,
+            1.0 - (1.0 - self.metallic) * self.transmission,
+            (1.0 - self.metallic) * self.transmission,
+            0.25 * self.clearcoat,
+        ]
+    }
+
+    // probabilities of sampling each lobe
+    fn lobe_probabilities(&self, wo: &Vec3) -> [f32; 4] {
+        let weights = self.weights();
+        let schlick = schlick_weight(wo.z);
+        let specular_albedo = luminance(&lerp(
+            &self.specular_f0(),
+            &Color::new_rgb(1.0, 1.0, 1.0),
+            schlick,
+        ));
+        let mut p = [
+            weights[0] * luminance(&self.base_color).max(self.sheen),
+            weights[1] * specular_albedo,
+            weights[2],
+            weights[3] * (0.04 + 0.96 * schlick),
+        ];
+        let total: f32 = p.iter().sum();
+        if total > 0.0 {
+            for x in p.iter_mut() {
+                *x /= total;
+            }
+        }
+        p
+    }
+
+    fn eval_local(&self, wo: &Vec3, wi: &Vec3, eta: f32) -> Color {
+        let weights = self.weights();
+        let mut f = Color::zero();
+        if wi.z > 0.0 {
+            let wh = (wo + wi).unit_norm();
+            let cos_d = wi.dot(&wh);
+            if weights[0] > 0.0 {
+                let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+                let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z))
+                    * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+                let sheen_color = lerp(
+                    &Color::new_rgb(1.0, 1.0, 1.0),
+                    &self.tint(),
+                    self.sheen_tint,
+                )
+                .scalar_mul(self.sheen * schlick_weight(cos_d));
+                let diffuse = self.base_color.scalar_mul(retro / PI) + sheen_color;
+                f += diffuse.scalar_mul(weights[0] * wi.z);
+            }
+            if let Some((f_cos, _, wm)) = self.specular_distribution().reflection(wo, wi) {
+                let fresnel = lerp(
+                    &self.specular_f0(),
+                    &Color::new_rgb(1.0, 1.0, 1.0),
+                    schlick_weight(wo.dot(&wm)),
+                );
+                f += fresnel.scalar_mul(weights[1] * f_cos);
+                if weights[2] > 0.0 {
+                    let fresnel = fresnel_dielectric(wo.dot(&wm), eta);
+                    f += Color::new(Vec3::iso(weights[2] * fresnel * f_cos));
+                }
+            }
+            if weights[3] > 0.0 {
+                if let Some((f_cos, _, wm)) = self.clearcoat_distribution().reflection(wo, wi) {
+                    let fresnel = 0.04 + 0.96 * schlick_weight(wo.dot(&wm));
+                    f += Color::new(Vec3::iso(weights[3] * fresnel * f_cos));
+                }
+            }
+        } else if weights[2] > 0.0 {
+            if let Some((f_cos, _, wm)) = self.specular_distribution().transmission(wo, wi, eta) {
+                let fresnel = fresnel_dielectric(wo.dot(&wm), eta);
+                f += self
+                    .base_color
+                    .scalar_mul(weights[2] * (1.0 - fresnel) * f_cos);
+            }
+        }
+        f
+    }
+
+    fn pdf_local(&self, wo: &Vec3, wi: &Vec3, eta: f32) -> f32 {
+        let p = self.lobe_probabilities(wo);
+        let specular = self.specular_distribution();
+        let mut pdf = 0.0;
+        if wi.z > 0.0 {
+            pdf += p[0] * cosine_hemisphere_pdf(wi.z);
+            if let Some((_, specular_pdf, wm)) = specular.reflection(wo, wi) {
+                pdf += p[1] * specular_pdf;
+                pdf += p[2] * fresnel_dielectric(wo.dot(&wm), eta) * specular_pdf;
+            }
+            if let Some((_, clearcoat_pdf, _)) = self.clearcoat_distribution().reflection(wo, wi) {
+                pdf += p[3] * clearcoat_pdf;
+            }
+        } else if let Some((_, glass_pdf, wm)) = specular.transmission(wo, wi, eta) {
+            pdf += p[2] * (1.0 - fresnel_dielectric(wo.dot(&wm), eta)) * glass_pdf;
+        }
+        pdf
+    }
+
+    fn eta(&self, hit_record: &HitRecord) -> f32 {
+        if hit_record.front_face {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
+}
+
+impl Bsdf for Principled {
+    fn sample(&self, wo: &Vec3, hit_record: &HitRecord, r: &mut Random) -> Option<BsdfSample> {
+        let frame = hit_record.shading_frame();
+        let wo_local = frame.to_local(wo);
+        if wo_local.z <= 0.0 {
+            return None;
+        }
+        let eta = self.eta(hit_record);
+        let p = self.lobe_probabilities(&wo_local);
+        let u = r.random_double();
+        let (wi, flags) = if u < p[0] {
+            (
+                Vec3::random_cosine_direction(r),
+                BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+            )
+        } else if u < p[0] + p[1] {
+            let wi = self
+                .specular_distribution()
+                .sample_reflection(&wo_local, r)?;
+            (wi, BsdfFlags::GLOSSY | BsdfFlags::REFLECTION)
+        } else if u < p[0] + p[1] + p[2] {
+            let wm = self.specular_distribution().sample_visible(&wo_local, r);
+            let reflectance = fresnel_dielectric(wo_local.dot(&wm), eta);
+            match refract(&wo_local, &wm, eta) {
+                Some(wi) if r.random_double() >= reflectance => {
+                    (wi, BsdfFlags::GLOSSY | BsdfFlags::TRANSMISSION)
+                }
+                _ => (
+                    (-&wo_local).reflect(&wm),
+                    BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+                ),
+            }
+        } else {
+            let wi = self
+                .clearcoat_distribution()
+                .sample_reflection(&wo_local, r)?;
+            (wi, BsdfFlags::GLOSSY | BsdfFlags::REFLECTION)
+        };
+        // weighted by the whole mixture, whichever lobe drew the direction
+        let pdf = self.pdf_local(&wo_local, &wi, eta);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: frame.local(&wi),
+            weight: self.eval_local(&wo_local, &wi, eta).scalar_mul(1.0 / pdf),
+            pdf,
+            flags,
+        })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit_record: &HitRecord) -> Color {
+        let frame = hit_record.shading_frame();
+        self.eval_local(
+            &frame.to_local(wo),
+            &frame.to_local(wi),
+            self.eta(hit_record),
+        )
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit_record: &HitRecord) -> f32 {
+        let frame = hit_record.shading_frame();
+        self.pdf_local(
+            &frame.to_local(wo),
+            &frame.to_local(wi),
+            self.eta(hit_record),
+        )
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Color {
+        if hit_record.front_face {
+            self.emission.clone()
+        } else {
+            Color::zero()
+        }
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        let mut flags = BsdfFlags::GLOSSY | BsdfFlags::REFLECTION;
+        if self.weights()[0] > 0.0 {
+            flags = flags | BsdfFlags::DIFFUSE;
+        }
+        if self.weights()[2] > 0.0 {
+            flags = flags | BsdfFlags::TRANSMISSION;
+        }
+        flags
+    }
+}
+
+fn luminance(color: &Color) -> f32 {
+    0.2126 * color.rgb.x + 0.7152 * color.rgb.y + 0.0722 * color.rgb.z
+}
+
+fn lerp(a: &Color, b: &Color, t: f32) -> Color {
+    a.scalar_mul(1.0 - t) + b.scalar_mul(t)
+}
+
+fn schlick_weight(cos: f32) -> f32 {
+    (1.0 - cos).clamp(0.0, 1.0).powi(5)
+}
+
+/// Power heuristic (beta = 2) multiple importance sampling weight of a
+/// sample drawn with density `pdf_f` against an alternative density `pdf_g`.
+pub fn power_heuristic(pdf_f: f32, pdf_g: f32) -> f32 {
+    let f = pdf_f * pdf_f;
+    let g = pdf_g * pdf_g;
+    if f + g == 0.0 {
+        0.0
+    } else {
+        f / (f + g)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracing::image::Image;
+
+    // hit on a floor facing +y, seen from `wo`
+    fn floor_hit<'a>(material: &'a Material, wo: &Vec3) -> HitRecord<'a> {
+        let ray = Ray::new(Point(wo.clone()), Point(-wo), 0.0);
+        HitRecord::new(
+            Point(Vec3::iso(0.0)),
+            1.0,
+            Point(Vec3::new(0.0, 1.0, 0.0)),
+            (0.5, 0.5),
+            material,
+            &ray,
+        )
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-3 * a.abs().max(b.abs()).max(1.0)
+    }
+
+    // samples agree with `eval` and `pdf` in the same direction
+    fn assert_consistent(material: &Material, wo: &Vec3) {
+        let rec = floor_hit(material, wo);
+        let mut r = Random::seeded(1);
+        let mut sampled = 0;
+        for _ in 0..1000 {
+            let sample = match material.sample(wo, &rec, &mut r) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            sampled += 1;
+            let pdf = material.pdf(wo, &sample.direction, &rec);
+            assert!(close(sample.pdf, pdf), "{} {}", sample.pdf, pdf);
+            let expected = material
+                .eval(wo, &sample.direction, &rec)
+                .scalar_mul(1.0 / pdf);
+            for (a, b) in [
+                (sample.weight.rgb.x, expected.rgb.x),
+                (sample.weight.rgb.y, expected.rgb.y),
+                (sample.weight.rgb.z, expected.rgb.z),
+            ] {
+                assert!(close(a, b), "{:?} {:?}", sample.weight, expected);
+            }
+        }
+        assert!(sampled > 500);
+    }
+
+    fn principled_variants() -> Vec<Principled> {
+        let base = Principled {
+            base_color: Color::new_rgb(0.9, 0.4, 0.1),
+            sheen: 0.5,
+            ..Principled::default()
+        };
+        vec![
+            base.clone(),
+            Principled {
+                metallic: 0.7,
+                anisotropic: 0.6,
+                ..base.clone()
+            },
+            Principled {
+                transmission: 0.8,
+                roughness: 0.2,
+                ..base.clone()
+            },
+            Principled {
+                clearcoat: 1.0,
+                specular_tint: 1.0,
+                ..base
+            },
+        ]
+    }
+
+    // uniform over the hemisphere, unlike every built-in diffuse lobe
+    struct UniformDiffuse {
+        albedo: Color,
+        emit: Color,
+    }
+
+    impl Bsdf for UniformDiffuse {
+        fn sample(&self, _wo: &Vec3, rec: &HitRecord, r: &mut Random) -> Option<BsdfSample> {
+            let mut direction = Vec3::random_unit_vector(r);
+            if direction.dot(&rec.normal.0) < 0.0 {
+                direction = -&direction;
+            }
+            let cosine = direction.dot(&rec.normal.0);
+            Some(BsdfSample {
+                direction,
+                weight: self.albedo.scalar_mul(2.0 * cosine),
+                pdf: 1.0 / (2.0 * PI),
+                flags: BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+            })
+        }
+
+        fn eval(&self, _wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> Color {
+            self.albedo.scalar_mul(wi.dot(&rec.normal.0).max(0.0) / PI)
+        }
+
+        fn pdf(&self, _wo: &Vec3, wi: &Vec3, rec: &HitRecord) -> f32 {
+            if wi.dot(&rec.normal.0) > 0.0 {
+                1.0 / (2.0 * PI)
+            } else {
+                0.0
+            }
+        }
+
+        fn emitted(&self, _rec: &HitRecord) -> Color {
+            self.emit.clone()
+        }
+
+        fn flags(&self) -> BsdfFlags {
+            BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION
+        }
+    }
+
+    #[test]
+    fn test_custom_bsdf() {
+        let material = Material::custom(UniformDiffuse {
+            albedo: Color::new_rgb(0.2, 0.5, 0.8),
+            emit: Color::new_rgb(1.0, 2.0, 3.0),
+        });
+        assert_eq!(material.flags(), BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION);
+        assert!(!material.flags().is_delta());
+        let wo = Vec3::new(0.6, 0.8, 0.0);
+        assert_consistent(&material, &wo);
+        let rec = floor_hit(&material, &wo);
+        assert_eq!(material.emitted(&rec).rgb, Vec3::new(1.0, 2.0, 3.0));
+        let below = Vec3::new(0.0, -1.0, 0.0);
+        assert_eq!(material.pdf(&wo, &below, &rec), 0.0);
+        assert!(material.eval(&wo, &below, &rec).is_black());
+    }
+
+    #[test]
+    fn test_principled_sampling() {
+        let wo = Vec3::new(0.3, 0.8, -0.2).unit_norm();
+        for principled in principled_variants() {
+            let material = Material::new_principled(principled.clone());
+            let local = floor_hit(&material, &wo).shading_frame().to_local(&wo);
+            let total: f32 = principled.lobe_probabilities(&local).iter().sum();
+            assert!((total - 1.0).abs() < 1e-5, "{}", total);
+            assert_consistent(&material, &wo);
+        }
+    }
+
+    #[test]
+    fn test_absorbing_dielectric() {
+        let color = Color::new_rgb(0.8, 0.5, 0.1);
+        let glass = Material::new_absorbing_dielectric(1.5, color.clone(), 2.0);
+        // leaving the glass, after the segment inside of it
+        let inside = floor_hit(&glass, &Vec3::new(0.0, -1.0, 0.0));
+        assert!(!inside.front_face);
+        let check = |distance: f32, expected: &Color| {
+            let t = glass.transmittance(&inside, distance);
+            assert!((&t.rgb - &expected.rgb).length() < 1e-5, "{:?}", t);
+        };
+        check(2.0, &color);
+        check(4.0, &(&color * &color));
+        check(0.0, &Color::new_rgb(1.0, 1.0, 1.0));
+        // entering, the segment was outside
+        let outside = floor_hit(&glass, &Vec3::new(0.0, 1.0, 0.0));
+        assert!(outside.front_face);
+        let t = glass.transmittance(&outside, 2.0);
+        assert_eq!(t.rgb, Vec3::iso(1.0));
+    }
+
+    #[test]
+    fn test_diffuse_sampling() {
+        let color = Color::new_rgb(0.9, 0.4, 0.1);
+        for wo in [
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.6, 0.5, -0.3).unit_norm(),
+            Vec3::new(-0.9, 0.1, 0.2).unit_norm(),
+        ] {
+            assert_consistent(&Material::new_oren_nayar(color.clone(), 20.0), &wo);
+            assert_consistent(
+                &Material::new_translucent(color.clone(), Color::new_rgb(0.1, 0.3, 0.6)),
+                &wo,
+            );
+        }
+        // transmitted half the time when both sides are alike
+        let grey = Color::new_rgb(0.5, 0.5, 0.5);
+        let leaf = Material::new_translucent(grey.clone(), grey);
+        let wo = Vec3::new(0.0, 1.0, 0.0);
+        let rec = floor_hit(&leaf, &wo);
+        let mut r = Random::seeded(1);
+        let below = (0..10_000)
+            .filter_map(|_| leaf.sample(&wo, &rec, &mut r))
+            .filter(|sample| sample.direction.y < 0.0)
+            .count();
+        assert!((4500..5500).contains(&below), "{}", below);
+    }
+
+    #[test]
+    fn test_microfacet_sampling() {
+        let copper = Material::new_conductor(ComplexIor::copper(), Ggx::anisotropic(0.2, 0.5));
+        let glass = Material::new_rough_dielectric(Ior::Constant(1.5), Ggx::isotropic(0.3));
+        for wo in [
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.6, 0.5, -0.3).unit_norm(),
+            Vec3::new(-0.9, 0.15, 0.2).unit_norm(),
+        ] {
+            assert_consistent(&copper, &wo);
+            assert_consistent(&glass, &wo);
+            // from inside the glass too
+            assert_consistent(&glass, &-&wo);
+        }
+        // both sides of the glass are sampled
+        let wo = Vec3::new(0.3, 0.8, 0.0).unit_norm();
+        let rec = floor_hit(&glass, &wo);
+        let mut r = Random::seeded(1);
+        let transmitted = (0..1000)
+            .filter_map(|_| glass.sample(&wo, &rec, &mut r))
+            .filter(|sample| sample.flags.contains(BsdfFlags::TRANSMISSION))
+            .inspect(|sample| assert!(sample.direction.y < 0.0))
+            .count();
+        assert!((800..1000).contains(&transmitted), "{}", transmitted);
+    }
+
+    #[test]
+    fn test_layered_sampling() {
+        let red = Material::new_lambertian(Color::new_rgb(0.9, 0.1, 0.1));
+        let gold = Material::new_conductor(ComplexIor::gold(), Ggx::isotropic(0.3));
+        let mix = Material::new_mix(red.clone(), gold, Texture::constant(0.3));
+        let coated = Material::new_coated(red, 1.5, Ggx::isotropic(0.2));
+        for wo in [
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.6, 0.5, -0.3).unit_norm(),
+            Vec3::new(-0.9, 0.15, 0.2).unit_norm(),
+        ] {
+            assert_consistent(&mix, &wo);
+            assert_consistent(&coated, &wo);
+        }
+    }
+
+    #[test]
+    fn test_normal_perturbation() {
+        let gray = Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5));
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let rec = floor_hit(&gray, &up);
+        let tangent = rec.tangent.clone();
+        let near = |a: &Vec3, b: &Vec3| (a - b).length() < 1e-3;
+        let map = |c: Color| NormalPerturbation::NormalMap {
+            texture: Texture::Solid(c),
+            strength: 1.0,
+        };
+
+        // the flat colour of normal maps leaves the normal alone
+        let flat = map(Color::new_rgb(0.5, 0.5, 1.0));
+        assert!(near(&flat.apply(&up, &rec).normal.0, &up));
+        // tilted towards the tangent, mirrored on back faces
+        let tilted = map(Color::new_rgb(1.0, 0.5, 1.0));
+        let expected = (&tangent + &up).unit_norm();
+        assert!(near(&tilted.apply(&up, &rec).normal.0, &expected));
+        let below = -&up;
+        let back = floor_hit(&gray, &below);
+        assert!(near(&tilted.apply(&below, &back).normal.0, &-&expected));
+        // turned away from the viewer, the geometric normal is kept
+        let grazing = (&tangent.scalar_mul(-1.0) + &up.scalar_mul(0.1)).unit_norm();
+        let rec = floor_hit(&gray, &grazing);
+        assert!(near(&tilted.apply(&grazing, &rec).normal.0, &up));
+
+        // a height rising along u bends the normal back towards -u
+        let ramp = Image::new(2, 1, vec![Vec3::iso(0.0), Vec3::iso(1.0)]);
+        let bump = NormalPerturbation::BumpMap {
+            height: Texture::Image(Arc::new(ramp)),
+            scale: 0.5,
+        };
+        let rec = floor_hit(&gray, &up);
+        let expected = (&up - &tangent).unit_norm();
+        assert!(near(&bump.apply(&up, &rec).normal.0, &expected));
+
+        // light under the geometric surface does not leak through the
+        // bent one
+        let bumpy = Material::new_perturbed(gray.clone(), tilted);
+        let wi = (&tangent + &up.scalar_mul(-0.1)).unit_norm();
+        assert!(bumpy.eval(&up, &wi, &rec).is_black());
+        assert_eq!(bumpy.pdf(&up, &wi, &rec), 0.0);
     }
 }
@@ -0,0 +1,359 @@
+use std::sync::Arc;
+
+use super::color::*;
+use super::geom::*;
+use super::material::*;
+use super::rand::*;
+use super::ray::*;
+use super::spectrum::*;
+use super::volume::*;
+
+/// Participating medium, like fog, smoke or murky water.
+///
+/// It fills closed objects made of `Material::MediumInterface`, or the
+/// whole scene as `HittableList::fog`.
+#[derive(Clone)]
+pub struct Medium {
+    /// Absorption coefficient per unit length.
+    pub sigma_a: Color,
+    /// Scattering coefficient per unit length.
+    pub sigma_s: Color,
+    /// Phase function of the scattering, a `Material::HenyeyGreenstein`.
+    pub phase: Material,
+    /// Density scaling the coefficients over space, homogeneous when
+    /// `None`.
+    pub grid: Option<DensityGrid>,
+}
+
+/// Voxel data of a heterogeneous medium.
+#[derive(Clone)]
+pub struct DensityGrid {
+    pub density: Arc<VoxelGrid>,
+    /// World space box the grids are stretched over, the medium is empty
+    /// outside of it.
+    pub bounds: Aabb,
+    /// Temperature in kelvin, glowing as a black body where the medium
+    /// absorbs.
+    pub temperature: Option<Arc<VoxelGrid>>,
+    /// Scales the black body radiance, which is in W/(m² sr nm).
+    pub emission_scale: f32,
+}
+
+impl DensityGrid {
+    fn local(&self, p: &Vec3) -> Vec3 {
+        let extent = &self.bounds.max.0 - &self.bounds.min.0;
+        let offset = p - &self.bounds.min.0;
+        Vec3::new(
+            offset.x / extent.x,
+            offset.y / extent.y,
+            offset.z / extent.z,
+        )
+    }
+}
+
+/// Outcome of a free flight through a medium.
+pub struct Flight {
+    /// Distance of the scattering event, `None` if the flight reached the
+    /// surface or was absorbed.
+    pub scatter: Option<f32>,
+    /// Factor for the path throughput, black when absorbed.
+    pub weight: Color,
+    /// Radiance emitted towards the ray origin, relative to the throughput
+    /// at the start of the flight.
+    pub emitted: Color,
+}
+
+impl Medium {
+    // ratio tracking transmittance under which shadow rays play roulette
+    const ROULETTE_THRESHOLD: f32 = 0.1;
+    const ROULETTE_SURVIVAL: f32 = 0.5;
+
+    /// `absorption` and `scattering` colours scaled by `density`, and
+    /// Henyey-Greenstein anisotropy `g` in `(-1, 1)`: positive scatters
+    /// forward, zero is isotropic.
+    pub fn new(density: f32, absorption: Color, scattering: Color, g: f32) -> Medium {
+        Medium {
+            sigma_a: absorption.scalar_mul(density),
+            sigma_s: scattering.scalar_mul(density),
+            phase: Material::HenyeyGreenstein { g },
+            grid: None,
+        }
+    }
+
+    /// Makes the coefficients the ones at unit density, scaled by
+    /// `density` stretched over `bounds`.
+    pub fn with_density(mut self, density: Arc<VoxelGrid>, bounds: Aabb) -> Medium {
+        self.grid = Some(DensityGrid {
+            density,
+            bounds,
+            temperature: None,
+            emission_scale: 0.0,
+        });
+        self
+    }
+
+    /// Black body emission from a grid of temperatures in kelvin over the
+    /// same bounds as the density. Panics without `with_density` first.
+    pub fn with_temperature(mut self, temperature: Arc<VoxelGrid>, emission_scale: f32) -> Medium {
+        let grid = self
+            .grid
+            .as_mut()
+            .expect("temperature needs a density grid");
+        grid.temperature = Some(temperature);
+        grid.emission_scale = emission_scale;
+        self
+    }
+
+    fn coefficients(&self, wavelengths: Option<&Wavelengths>) -> (Color, Color) {
+        let sigma_s = upsample(wavelengths, self.sigma_s.clone());
+        let sigma_t = upsample(wavelengths, self.sigma_a.clone()) + sigma_s.clone();
+        (sigma_s, sigma_t)
+    }
+
+    /// Fraction of light going `distance` along `ray` through the medium
+    /// unscattered. Estimated with ratio tracking in grids.
+    pub fn transmittance(&self, ray: &Ray, distance: f32, r: &mut Random) -> Color {
+        let (_, sigma_t) = self.coefficients(ray.wavelengths.as_ref());
+        let grid = match &self.grid {
+            Some(grid) => grid,
+            None => return beer_lambert(&sigma_t, distance),
+        };
+        let direction = ray.direction.0.unit_norm();
+        let mut transmittance = Color::new_rgb(1.0, 1.0, 1.0);
+        let (mut t, t_max) = match grid
+            .bounds
+            .overlap(&ray.origin.0, &direction, 0.0, distance)
+        {
+            Some(segment) => segment,
+            None => return transmittance,
+        };
+        let majorant = sigma_t.max_component() * grid.density.max_value();
+        if majorant <= 0.0 {
+            return transmittance;
+        }
+        loop {
+            t -= (1.0 - r.random_double()).ln() / majorant;
+            if t >= t_max {
+                return transmittance;
+            }
+            let p = &ray.origin.0 + &direction.scalar_mul(t);
+            let density = grid.density.lookup(&grid.local(&p));
+            let null = Color::new(&Vec3::iso(1.0) - &sigma_t.rgb.scalar_mul(density / majorant));
+            transmittance = &transmittance * &null;
+            // Russian roulette rather than a cutoff, which would darken
+            // shadows behind thick media
+            if transmittance.max_component() < Medium::ROULETTE_THRESHOLD {
+                if r.random_double() >= Medium::ROULETTE_SURVIVAL {
+                    return Color::zero();
+                }
+                transmittance = transmittance.scalar_mul(1.0 / Medium::ROULETTE_SURVIVAL);
+            }
+        }
+    }
+
+    /// Free flight along `ray` reaching a surface after `max_distance`.
+    /// Homogeneous distances are sampled on a random channel and weighted
+    /// by the average density over the channels, grids use spectral delta
+    /// tracking (Kutz et al. 2017).
+    pub fn sample_flight(&self, ray: &Ray, max_distance: f32, r: &mut Random) -> Flight {
+        if let Some(grid) = &self.grid {
+            return self.track(grid, ray, max_distance, r);
+        }
+        let (sigma_s, sigma_t) = self.coefficients(ray.wavelengths.as_ref());
+        let channels = [sigma_t.rgb.x, sigma_t.rgb.y, sigma_t.rgb.z];
+        let channel = ((r.random_double() * 3.0) as usize).min(2);
+        let distance = if channels[channel] > 0.0 {
+            -(1.0 - r.random_double()).ln() / channels[channel]
+        } else {
+            INFINITY
+        };
+        if distance < max_distance {
+            let transmittance = beer_lambert(&sigma_t, distance);
+            let pdf = mean(&(&sigma_t * &transmittance));
+            Flight {
+                scatter: Some(distance),
+                weight: (&transmittance * &sigma_s).scalar_mul(1.0 / pdf),
+                emitted: Color::zero(),
+            }
+        } else {
+            let transmittance = beer_lambert(&sigma_t, max_distance);
+            let pdf = mean(&transmittance);
+            Flight {
+                scatter: None,
+                weight: if pdf > 0.0 {
+                    transmittance.scalar_mul(1.0 / pdf)
+                } else {
+                    Color::zero()
+                },
+                emitted: Color::zero(),
+            }
+        }
+    }
+
+    // tentative collisions at the majorant density, each one absorbing,
+    // scattering or null with probabilities from the mean coefficients and
+    // weights correcting for the other channels
+    fn track(&self, grid: &DensityGrid, ray: &Ray, max_distance: f32, r: &mut Random) -> Flight {
+        let wavelengths = ray.wavelengths.as_ref();
+        let (sigma_s, sigma_t) = self.coefficients(wavelengths);
+        let sigma_a = upsample(wavelengths, self.sigma_a.clone());
+        let direction = ray.direction.0.unit_norm();
+        let mut flight = Flight {
+            scatter: None,
+            weight: Color::new_rgb(1.0, 1.0, 1.0),
+            emitted: Color::zero(),
+        };
+        let (mut t, t_max) = match grid
+            .bounds
+            .overlap(&ray.origin.0, &direction, 0.0, max_distance)
+        {
+            Some(segment) => segment,
+            None => return flight,
+        };
+        let majorant = sigma_t.max_component() * grid.density.max_value();
+        if majorant <= 0.0 {
+            return flight;
+        }
+        loop {
+            t -= (1.0 - r.random_double()).ln() / majorant;
+            if t >= t_max {
+                return flight;
+            }
+            let p = &ray.origin.0 + &direction.scalar_mul(t);
+            let local = grid.local(&p);
+            let density = grid.density.lookup(&local);
+            let absorption = sigma_a.scalar_mul(density);
+            let scattering = sigma_s.scalar_mul(density);
+            let null = Color::new(&Vec3::iso(majorant) - &sigma_t.rgb.scalar_mul(density));
+            if let Some(temperature) = &grid.temperature {
+                let kelvin = temperature.lookup(&local);
+                if kelvin > 0.0 {
+                    let radiance =
+                        blackbody(wavelengths, kelvin).scalar_mul(grid.emission_scale / majorant);
+                    flight.emitted += &(&flight.weight * &absorption) * &radiance;
+                }
+            }
+            let p_absorb = mean(&absorption) / majorant;
+            let p_scatter = mean(&scattering) / majorant;
+            let p_null = mean(&null) / majorant;
+            let u = r.random_double() * (p_absorb + p_scatter + p_null);
+            if u < p_absorb {
+                flight.weight = Color::zero();
+                return flight;
+            } else if u < p_absorb + p_scatter {
+                flight.weight =
+                    (&flight.weight * &scattering).scalar_mul(1.0 / (majorant * p_scatter));
+                flight.scatter = Some(t);
+                return flight;
+            }
+            flight.weight = (&flight.weight * &null).scalar_mul(1.0 / (majorant * p_null));
+            if flight.weight.is_black() {
+                return flight;
+            }
+        }
+    }
+
+    /// Scattering event at `t` along `ray`, shaded by the phase function.
+    pub fn scattering_record<'a>(&'a self, ray: &Ray, t: f32) -> HitRecord<'a> {
+        HitRecord::new(
+            ray.at(t),
+            t,
+            Point(-&ray.direction.0.unit_norm()),
+            (0.0, 0.0),
+            &self.phase,
+            ray,
+        )
+    }
+}
+
+fn mean(color: &Color) -> f32 {
+    (color.rgb.x + color.rgb.y + color.rgb.z) / 3.0
+}
+
+fn beer_lambert(sigma_t: &Color, distance: f32) -> Color {
+    // clear channels stay clear even over infinite distances
+    let channel = |sigma: f32| {
+        if sigma > 0.0 {
+            (-sigma * distance).exp()
+        } else {
+            1.0
+        }
+    };
+    Color::new_rgb(
+        channel(sigma_t.rgb.x),
+        channel(sigma_t.rgb.y),
+        channel(sigma_t.rgb.z),
+    )
+}
+
+/// Henyey-Greenstein phase function for the cosine between the direction
+/// light travels in and the scattered direction.
+pub fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.max(1e-12).sqrt())
+}
+
+/// Samples the cosine of `henyey_greenstein`.
+pub fn sample_henyey_greenstein(g: f32, r: &mut Random) -> f32 {
+    let u = r.random_double();
+    if g.abs() < 1e-3 {
+        return 1.0 - 2.0 * u;
+    }
+    let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+    ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_henyey_greenstein_normalised() {
+        for &g in &[-0.5, 0.0, 0.8] {
+            let n = 10000;
+            let integral: f32 = (0..n)
+                .map(|i| {
+                    let cos = -1.0 + 2.0 * (i as f32 + 0.5) / n as f32;
+                    henyey_greenstein(cos, g) * 2.0 * PI * 2.0 / n as f32
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-2, "g {} gives {}", g, integral);
+        }
+    }
+
+    #[test]
+    fn test_grid_transmittance_matches_beer_lambert() {
+        // constant density, so only the colour makes collisions null
+        let grid = Arc::new(VoxelGrid::new(2, 2, 2, vec![0.5; 8]));
+        let bounds = Aabb::new(Point(Vec3::iso(0.0)), Point(Vec3::new(2.0, 1.0, 1.0)));
+        let sigma_a = Color::new_rgb(6.0, 3.0, 1.0);
+        let sigma_s = Color::new_rgb(2.0, 1.0, 1.0);
+        let medium = Medium::new(1.0, sigma_a, sigma_s, 0.0).with_density(grid, bounds);
+        let ray = Ray::new(
+            Point(Vec3::new(-1.0, 0.5, 0.5)),
+            Point(Vec3::new(1.0, 0.0, 0.0)),
+            0.0,
+        );
+        // through the whole grid, sigma_t of (4, 2, 1) over 2
+        let expected = beer_lambert(&Color::new_rgb(4.0, 2.0, 1.0), 2.0);
+        let mut r = Random::seeded(1);
+        let n = 200_000;
+        let mut sum = Color::zero();
+        for _ in 0..n {
+            sum += medium.transmittance(&ray, 4.0, &mut r);
+        }
+        let estimate = sum.scalar_mul(1.0 / n as f32);
+        for (a, b) in [
+            (estimate.rgb.x, expected.rgb.x),
+            (estimate.rgb.y, expected.rgb.y),
+            (estimate.rgb.z, expected.rgb.z),
+        ] {
+            assert!(
+                (a - b).abs() < 0.03 * b + 2e-4,
+                "{:?} {:?}",
+                estimate,
+                expected
+            );
+        }
+    }
+}
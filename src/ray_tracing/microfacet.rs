@@ -0,0 +1,259 @@
+use super::color::*;
+use super::geom::*;
+use super::rand::*;
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals.
+///
+/// All directions are in the shading frame, the macro normal is `+z` and
+/// the first tangent `+x`. `alpha_x` and `alpha_y` are the roughnesses
+/// along the tangents.
+#[derive(Debug, Clone)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    // below this the surface is handled as a perfect mirror
+    const SMOOTH_ALPHA: f32 = 1e-3;
+
+    /// `roughness` is perceptual, the distribution width is its square.
+    pub fn isotropic(roughness: f32) -> Ggx {
+        Ggx::anisotropic(roughness, roughness)
+    }
+
+    pub fn anisotropic(roughness_x: f32, roughness_y: f32) -> Ggx {
+        Ggx {
+            alpha_x: roughness_x * roughness_x,
+            alpha_y: roughness_y * roughness_y,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < Ggx::SMOOTH_ALPHA
+    }
+
+    /// Density of microfacet normals `wm`, per unit projected area.
+    pub fn d(&self, wm: &Vec3) -> f32 {
+        if wm.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (wm.x / self.alpha_x).powi(2) + (wm.y / self.alpha_y).powi(2) + wm.z * wm.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f32 {
+        if w.z == 0.0 {
+            return f32::INFINITY;
+        }
+        let a2_tan2 = ((w.x * self.alpha_x).powi(2) + (w.y * self.alpha_y).powi(2)) / (w.z * w.z);
+        ((1.0 + a2_tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Smith masking of the microsurface seen from `w`.
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height correlated masking and shadowing.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals visible from `w`, what `sample_visible` draws.
+    pub fn d_visible(&self, w: &Vec3, wm: &Vec3) -> f32 {
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Samples a microfacet normal visible from `w` (Heitz 2018).
+    pub fn sample_visible(&self, w: &Vec3, r: &mut Random) -> Vec3 {
+        // to the hemisphere configuration
+        let mut wh = Vec3::new(w.x * self.alpha_x, w.y * self.alpha_y, w.z).unit_norm();
+        if wh.z < 0.0 {
+            wh = -&wh;
+        }
+        let t1 = if wh.z < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(&wh).unit_norm()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+        // disk point, warped towards the visible half
+        let p = Vec3::random_in_unit_disk(r);
+        let h = (1.0 - p.x * p.x).sqrt();
+        let s = (1.0 + wh.z) / 2.0;
+        let py = (1.0 - s) * h + s * p.y;
+        let pz = (1.0 - p.x * p.x - py * py).max(0.0).sqrt();
+        let nh = t1.scalar_mul(p.x) + t2.scalar_mul(py) + wh.scalar_mul(pz);
+        Vec3::new(nh.x * self.alpha_x, nh.y * self.alpha_y, nh.z.max(1e-6)).unit_norm()
+    }
+
+    /// Reflection off `wm`, `None` if it goes below the surface.
+    pub fn sample_reflection(&self, wo: &Vec3, r: &mut Random) -> Option<Vec3> {
+        let wm = self.sample_visible(wo, r);
+        let wi = (-wo).reflect(&wm);
+        if wi.z > 0.0 {
+            Some(wi)
+        } else {
+            None
+        }
+    }
+
+    /// Reflection lobe without Fresnel: `f * cos(wi)`, the density of
+    /// `sample_reflection` and the half vector. `None` when `wi` cannot be
+    /// reached.
+    pub fn reflection(&self, wo: &Vec3, wi: &Vec3) -> Option<(f32, f32, Vec3)> {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return None;
+        }
+        let wm = (wo + wi).unit_norm();
+        let f_cos = self.d(&wm) * self.g(wo, wi) / (4.0 * wo.z);
+        let pdf = self.d_visible(wo, &wm) / (4.0 * wo.dot(&wm));
+        Some((f_cos, pdf, wm))
+    }
+
+    /// Transmission lobe without Fresnel into a medium `eta` times denser
+    /// (Walter et al. 2007), like `reflection`. Radiance is not scaled by
+    /// `1 / eta²`, as for smooth dielectrics the factor cancels on paths
+    /// going in and out again.
+    pub fn transmission(&self, wo: &Vec3, wi: &Vec3, eta: f32) -> Option<(f32, f32, Vec3)> {
+        if wo.z <= 0.0 || wi.z >= 0.0 {
+            return None;
+        }
+        let mut wm = wi.scalar_mul(eta) + wo.clone();
+        if wm.length_squared() == 0.0 {
+            return None;
+        }
+        wm = wm.unit_norm();
+        if wm.z < 0.0 {
+            wm = -&wm;
+        }
+        // microfacets facing away from either direction
+        if wm.dot(wi) >= 0.0 || wm.dot(wo) <= 0.0 {
+            return None;
+        }
+        let denom = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+        let f_cos =
+            self.d(&wm) * self.g(wo, wi) * (wi.dot(&wm) * wo.dot(&wm)).abs() / (denom * wo.z);
+        let pdf = self.d_visible(wo, &wm) * wi.dot(&wm).abs() / denom;
+        Some((f_cos, pdf, wm))
+    }
+}
+
+/// Complex index of refraction of a conductor, per RGB channel.
+#[derive(Debug, Clone)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ComplexIor {
+    pub fn gold() -> ComplexIor {
+        ComplexIor {
+            eta: Color::new_rgb(0.143, 0.374, 1.442),
+            k: Color::new_rgb(3.983, 2.385, 1.603),
+        }
+    }
+
+    pub fn copper() -> ComplexIor {
+        ComplexIor {
+            eta: Color::new_rgb(0.200, 0.924, 1.102),
+            k: Color::new_rgb(3.912, 2.452, 2.142),
+        }
+    }
+
+    pub fn aluminium() -> ComplexIor {
+        ComplexIor {
+            eta: Color::new_rgb(1.657, 0.880, 0.521),
+            k: Color::new_rgb(9.224, 6.270, 4.837),
+        }
+    }
+
+    /// Reflectance at incidence cosine `cos_i`.
+    pub fn fresnel(&self, cos_i: f32) -> Color {
+        Color::new_rgb(
+            fresnel_conductor(cos_i, self.eta.rgb.x, self.k.rgb.x),
+            fresnel_conductor(cos_i, self.eta.rgb.y, self.k.rgb.y),
+            fresnel_conductor(cos_i, self.eta.rgb.z, self.k.rgb.z),
+        )
+    }
+}
+
+/// Unpolarised Fresnel reflectance of a conductor.
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / 2.0
+}
+
+/// Unpolarised Fresnel reflectance of a dielectric interface, `eta` is the
+/// index of the far side over the one of the near side and `cos_i` is
+/// negative when arriving from the far side.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_i < 0.0 {
+        (-cos_i, 1.0 / eta)
+    } else {
+        (cos_i, eta)
+    };
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+/// Refracts `w` (pointing away from the surface) through the interface
+/// with normal `n` on its side, `None` on total internal reflection.
+pub fn refract(w: &Vec3, n: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = n.dot(w);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(w.scalar_mul(-1.0 / eta) + n.scalar_mul(cos_i / eta - cos_t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ggx_normalised() {
+        // projected microfacet area integrates to one
+        let ggx = Ggx::anisotropic(0.5, 0.8);
+        let n = 400;
+        let mut sum = 0.0;
+        for i in 0..n {
+            let cos_theta = (i as f32 + 0.5) / n as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..n {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / n as f32;
+                let wm = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                sum += ggx.d(&wm) * cos_theta;
+            }
+        }
+        let integral = sum * 2.0 * PI / (n * n) as f32;
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+    }
+
+    #[test]
+    fn test_fresnel_normal_incidence() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+        let r = fresnel_conductor(1.0, 0.2, 3.9);
+        let expected = ((0.2f32 - 1.0).powi(2) + 3.9 * 3.9) / ((0.2f32 + 1.0).powi(2) + 3.9 * 3.9);
+        assert!((r - expected).abs() < 1e-4);
+    }
+}
@@ -1,7 +1,20 @@
+pub mod analytic;
+pub mod bvh;
 pub mod camera;
 pub mod color;
+pub mod csg;
 pub mod geom;
+pub mod heightfield;
+pub mod image;
+pub mod instance;
 pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod motion;
 pub mod object;
 pub mod rand;
 pub mod ray;
+pub mod sdf;
+pub mod spectrum;
+pub mod texture;
+pub mod volume;
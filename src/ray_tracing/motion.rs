@@ -0,0 +1,212 @@
+use super::geom::*;
+
+/// Decomposed object to world transform at a given time.
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f32, translation: Vec3, rotation: Quat, scale: Vec3) -> Keyframe {
+        Keyframe {
+            time,
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    /// Scale, then rotate, then translate.
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translation(&self.translation) * self.rotation.to_mat4() * Mat4::scaling(&self.scale)
+    }
+
+    pub fn inverse(&self) -> Mat4 {
+        let inverse_scale = Vec3::new(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z);
+        Mat4::scaling(&inverse_scale)
+            * self.rotation.conjugate().to_mat4()
+            * Mat4::translation(&-&self.translation)
+    }
+}
+
+/// Transform interpolated between keyframes: translation and scale
+/// linearly, rotation with quaternion slerp. Times outside the keyframes
+/// clamp to the first or last one.
+pub struct AnimatedTransform {
+    keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    // bounding boxes sample the motion this many times per keyframe interval
+    const BOUNDS_STEPS: u32 = 32;
+
+    /// Panics without keyframes.
+    pub fn new(mut keyframes: Vec<Keyframe>) -> AnimatedTransform {
+        assert!(
+            !keyframes.is_empty(),
+            "an animated transform needs at least a keyframe"
+        );
+        keyframes.sort_by(|a, b| {
+            a.time
+                .partial_cmp(&b.time)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        AnimatedTransform { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn at(&self, time: f32) -> Keyframe {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time <= first.time {
+            return first.clone();
+        }
+        if time >= last.time {
+            return last.clone();
+        }
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        let (k0, k1) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = (time - k0.time) / (k1.time - k0.time);
+        Keyframe {
+            time,
+            translation: k0.translation.scalar_mul(1.0 - t) + k1.translation.scalar_mul(t),
+            rotation: k0.rotation.slerp(&k1.rotation, t),
+            scale: k0.scale.scalar_mul(1.0 - t) + k1.scale.scalar_mul(t),
+        }
+    }
+
+    /// Box enclosing `bbox` moved by the transform over `[time_0, time_1]`.
+    ///
+    /// Rotations sweep curves, so the motion is sampled densely and the
+    /// result padded by the largest distance a corner can travel between
+    /// two samples.
+    pub fn motion_bounds(&self, bbox: &Aabb, time_0: f32, time_1: f32) -> Aabb {
+        let intervals = (self.keyframes.len() as u32).max(2) - 1;
+        let steps = Self::BOUNDS_STEPS * intervals;
+        let boxes: Vec<Aabb> = (0..=steps)
+            .map(|i| {
+                let time = time_0 + (time_1 - time_0) * i as f32 / steps as f32;
+                self.at(time).matrix().transform_aabb(bbox)
+            })
+            .collect();
+        let mut max_step = 0f32;
+        for pair in boxes.windows(2) {
+            let moved = (&pair[1].min.0 - &pair[0].min.0)
+                .length()
+                .max((&pair[1].max.0 - &pair[0].max.0).length());
+            max_step = max_step.max(moved);
+        }
+        let bounds = boxes
+            .into_iter()
+            .reduce(|a, b| a.surrounding(&b))
+            .unwrap_or_else(|| bbox.clone());
+        let pad = Vec3::iso(max_step);
+        Aabb::new(Point(&bounds.min.0 - &pad), Point(&bounds.max.0 + &pad))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spinning() -> AnimatedTransform {
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        AnimatedTransform::new(vec![
+            Keyframe::new(
+                0.0,
+                Vec3::iso(0.0),
+                Quat::from_axis_angle(&up, 0.0),
+                Vec3::iso(1.0),
+            ),
+            Keyframe::new(
+                1.0,
+                Vec3::new(2.0, 0.0, 0.0),
+                Quat::from_axis_angle(&up, 120.0),
+                Vec3::new(1.0, 2.0, 1.0),
+            ),
+            Keyframe::new(
+                2.0,
+                Vec3::new(2.0, 1.0, 0.0),
+                Quat::from_axis_angle(&up, 240.0),
+                Vec3::iso(1.0),
+            ),
+        ])
+    }
+
+    fn corners(bbox: &Aabb) -> Vec<Vec3> {
+        (0..8)
+            .map(|i| {
+                Vec3::new(
+                    if i & 1 == 0 { bbox.min.x } else { bbox.max.x },
+                    if i & 2 == 0 { bbox.min.y } else { bbox.max.y },
+                    if i & 4 == 0 { bbox.min.z } else { bbox.max.z },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_keyframes_and_slerp() {
+        let motion = spinning();
+        let p = Vec3::new(1.0, 0.5, -0.5);
+        for keyframe in motion.keyframes() {
+            let at = motion.at(keyframe.time);
+            let moved = at.matrix().transform_point(&p);
+            assert!((&moved - &keyframe.matrix().transform_point(&p)).length() < 1e-5);
+        }
+        // halfway between two keyframes is halfway around
+        let halfway = motion.at(1.5);
+        let x = halfway
+            .rotation
+            .to_mat4()
+            .transform_vector(&Vec3::new(1.0, 0.0, 0.0));
+        let expected = Quat::from_axis_angle(&Vec3::new(0.0, 1.0, 0.0), 180.0)
+            .to_mat4()
+            .transform_vector(&Vec3::new(1.0, 0.0, 0.0));
+        assert!((&x - &expected).length() < 1e-4, "{:?}", x);
+        assert!((&halfway.translation - &Vec3::new(2.0, 0.5, 0.0)).length() < 1e-5);
+        // and round trips through the inverse
+        let back = halfway
+            .inverse()
+            .transform_point(&halfway.matrix().transform_point(&p));
+        assert!((&back - &p).length() < 1e-4);
+    }
+
+    #[test]
+    fn test_motion_bounds_contain_the_motion() {
+        let motion = spinning();
+        // long along x, so the rotation sweeps far outside the keyframe boxes
+        let bbox = Aabb::new(
+            Point(Vec3::new(-0.5, -0.5, -0.5)),
+            Point(Vec3::new(3.0, 0.5, 0.5)),
+        );
+        for &(time_0, time_1) in [(0.0, 2.0), (0.3, 1.7), (1.2, 1.25)].iter() {
+            let bounds = motion.motion_bounds(&bbox, time_0, time_1);
+            for i in 0..=1000 {
+                let time = time_0 + (time_1 - time_0) * i as f32 / 1000.0;
+                let matrix = motion.at(time).matrix();
+                for corner in corners(&bbox) {
+                    let p = matrix.transform_point(&corner);
+                    assert!(
+                        p.x >= bounds.min.x
+                            && p.y >= bounds.min.y
+                            && p.z >= bounds.min.z
+                            && p.x <= bounds.max.x
+                            && p.y <= bounds.max.y
+                            && p.z <= bounds.max.z,
+                        "{:?} at {} outside {:?}",
+                        p,
+                        time,
+                        bounds
+                    );
+                }
+            }
+        }
+    }
+}
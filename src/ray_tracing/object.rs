@@ -1,9 +1,42 @@
-use crate::HitRecord;
-use crate::Material;
-use crate::Point;
-use crate::Ray;
+use super::analytic::AnalyticShape;
+use super::bvh::Bvh;
+use super::csg::{Csg, CsgOperation};
+use super::geom::*;
+use super::heightfield::Heightfield;
+use super::instance::{AnimatedInstance, Instance};
+use super::material::*;
+use super::motion::AnimatedTransform;
+use super::rand::*;
+use super::ray::*;
+use super::sdf::Sdf;
+use std::sync::Arc;
 use Object::*;
 
+/// Anything a ray can intersect.
+///
+/// The built-in primitives are variants of [`Object`]; downstream code can
+/// implement this trait for its own surfaces and wrap them with
+/// [`Object::custom`] to store them in a `HittableList` or a [`Bvh`].
+pub trait Hittable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
+
+    /// Box enclosing the surface over the `[time_0, time_1]` interval,
+    /// `None` for unbounded surfaces which are kept out of the BVH.
+    fn bounding_box(&self, time_0: f32, time_1: f32) -> Option<Aabb>;
+
+    /// Solid angle density of `random_direction` seen from `origin`.
+    /// Only needed for surfaces used as lights.
+    fn pdf_value(&self, _origin: &Point, _direction: &Vec3, _time: f32) -> f32 {
+        0.0
+    }
+
+    /// Samples a direction from `origin` towards the surface, `None` if the
+    /// surface cannot be sampled.
+    fn random_direction(&self, _origin: &Point, _time: f32, _r: &mut Random) -> Option<Vec3> {
+        None
+    }
+}
+
 pub enum Object {
     Sphere {
         center: Point,
@@ -11,6 +44,46 @@ pub enum Object {
         material: Material,
         moving_component: Option<MovingComponent>,
     },
+    /// Parallelogram with a corner in `q` and sides `u` and `v`.
+    Quad {
+        q: Point,
+        u: Vec3,
+        v: Vec3,
+        material: Material,
+    },
+    /// Infinite plane, UVs are world units along a tangent frame.
+    Plane {
+        point: Point,
+        normal: Vec3,
+        material: Material,
+    },
+    Disk {
+        center: Point,
+        normal: Vec3,
+        radius: f32,
+        material: Material,
+    },
+    /// Cylinder, cone, paraboloid, hyperboloid or torus around the `y`
+    /// axis through `center`, cut to `sweep` degrees around it.
+    Analytic {
+        shape: AnalyticShape,
+        center: Point,
+        sweep: f32,
+        material: Material,
+        moving_component: Option<MovingComponent>,
+    },
+    /// Implicit surface sphere traced within `bounds`.
+    Implicit {
+        sdf: Sdf,
+        bounds: Aabb,
+        material: Material,
+    },
+    Csg(Box<Csg>),
+    Heightfield(Box<Heightfield>),
+    Bvh(Box<Bvh>),
+    Instance(Box<Instance>),
+    AnimatedInstance(Box<AnimatedInstance>),
+    Custom(Box<dyn Hittable>),
 }
 
 pub struct MovingComponent {
@@ -21,7 +94,268 @@ pub struct MovingComponent {
 }
 
 impl Object {
-    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    pub fn custom<H: Hittable + 'static>(hittable: H) -> Object {
+        Custom(Box::new(hittable))
+    }
+
+    pub fn new_analytic(
+        shape: AnalyticShape,
+        center: Point,
+        sweep: f32,
+        material: Material,
+    ) -> Object {
+        Analytic {
+            shape,
+            center,
+            sweep,
+            material,
+            moving_component: None,
+        }
+    }
+
+    /// Analytic shape whose centre moves from `center_0` to `center_1` of
+    /// `motion`, for motion blur.
+    pub fn new_moving_analytic(
+        shape: AnalyticShape,
+        sweep: f32,
+        material: Material,
+        motion: MovingComponent,
+    ) -> Object {
+        Analytic {
+            shape,
+            center: motion.center_0.clone(),
+            sweep,
+            material,
+            moving_component: Some(motion),
+        }
+    }
+
+    /// Zero level set of `sdf`, which must lie inside `bounds`.
+    pub fn new_sdf(sdf: Sdf, bounds: Aabb, material: Material) -> Object {
+        Implicit {
+            sdf,
+            bounds,
+            material,
+        }
+    }
+
+    /// Boolean combination of closed objects, see [`Csg`].
+    pub fn new_csg(operation: CsgOperation, a: Arc<Object>, b: Arc<Object>) -> Object {
+        Csg(Box::new(Csg::new(operation, a, b)))
+    }
+
+    pub fn new_heightfield(heightfield: Heightfield) -> Object {
+        Heightfield(Box::new(heightfield))
+    }
+
+    /// Places `object` in the world through `transform`, see [`Instance`].
+    pub fn new_instance(object: Arc<Object>, transform: Mat4) -> Object {
+        Instance(Box::new(Instance::new(object, transform)))
+    }
+
+    /// Moves `object` along keyframed transforms, for motion blur of any
+    /// shape.
+    pub fn new_animated_instance(object: Arc<Object>, motion: AnimatedTransform) -> Object {
+        AnimatedInstance(Box::new(AnimatedInstance::new(object, motion)))
+    }
+
+    pub fn new_quad(q: Point, u: Vec3, v: Vec3, material: Material) -> Object {
+        Quad { q, u, v, material }
+    }
+
+    /// Rectangle on the plane `z = k`, facing +z.
+    pub fn new_xy_rect(x0: f32, x1: f32, y0: f32, y1: f32, k: f32, material: Material) -> Object {
+        Object::new_quad(
+            Point(Vec3::new(x0, y0, k)),
+            Vec3::new(x1 - x0, 0.0, 0.0),
+            Vec3::new(0.0, y1 - y0, 0.0),
+            material,
+        )
+    }
+
+    /// Rectangle on the plane `y = k`, facing +y.
+    pub fn new_xz_rect(x0: f32, x1: f32, z0: f32, z1: f32, k: f32, material: Material) -> Object {
+        Object::new_quad(
+            Point(Vec3::new(x0, k, z0)),
+            Vec3::new(0.0, 0.0, z1 - z0),
+            Vec3::new(x1 - x0, 0.0, 0.0),
+            material,
+        )
+    }
+
+    /// Rectangle on the plane `x = k`, facing +x.
+    pub fn new_yz_rect(y0: f32, y1: f32, z0: f32, z1: f32, k: f32, material: Material) -> Object {
+        Object::new_quad(
+            Point(Vec3::new(k, y0, z0)),
+            Vec3::new(0.0, y1 - y0, 0.0),
+            Vec3::new(0.0, 0.0, z1 - z0),
+            material,
+        )
+    }
+
+    pub fn new_plane(point: Point, normal: Vec3, material: Material) -> Object {
+        Plane {
+            point,
+            normal: normal.unit_norm(),
+            material,
+        }
+    }
+
+    pub fn new_disk(center: Point, normal: Vec3, radius: f32, material: Material) -> Object {
+        Disk {
+            center,
+            normal: normal.unit_norm(),
+            radius,
+            material,
+        }
+    }
+
+    /// Axis aligned box with opposite corners `a` and `b`, made of six
+    /// outward facing quads.
+    pub fn new_box(a: &Point, b: &Point, material: Material) -> Object {
+        let min = a.0.min(&b.0);
+        let max = a.0.max(&b.0);
+        let dx = Vec3::new(max.x - min.x, 0.0, 0.0);
+        let dy = Vec3::new(0.0, max.y - min.y, 0.0);
+        let dz = Vec3::new(0.0, 0.0, max.z - min.z);
+        let sides = vec![
+            // front, right, back, left, top, bottom
+            Object::new_quad(
+                Point(Vec3::new(min.x, min.y, max.z)),
+                dx.clone(),
+                dy.clone(),
+                material.clone(),
+            ),
+            Object::new_quad(
+                Point(Vec3::new(max.x, min.y, max.z)),
+                -&dz,
+                dy.clone(),
+                material.clone(),
+            ),
+            Object::new_quad(
+                Point(Vec3::new(max.x, min.y, min.z)),
+                -&dx,
+                dy.clone(),
+                material.clone(),
+            ),
+            Object::new_quad(
+                Point(Vec3::new(min.x, min.y, min.z)),
+                dz.clone(),
+                dy,
+                material.clone(),
+            ),
+            Object::new_quad(
+                Point(Vec3::new(min.x, max.y, max.z)),
+                dx.clone(),
+                -&dz,
+                material.clone(),
+            ),
+            Object::new_quad(Point(Vec3::new(min.x, min.y, min.z)), dx, dz, material),
+        ];
+        match Bvh::new(sides, 0.0, 0.0) {
+            Some(bvh) => Bvh(Box::new(bvh)),
+            None => unreachable!("a box always has six sides"),
+        }
+    }
+
+    pub fn center_at(&self, t: f32) -> Point {
+        match self {
+            Sphere {
+                moving_component,
+                center,
+                ..
+            }
+            | Analytic {
+                moving_component,
+                center,
+                ..
+            } => match moving_component {
+                Some(MovingComponent {
+                    center_0,
+                    center_1,
+                    time_0,
+                    time_1,
+                }) => Point(
+                    &center_0.0
+                        + &(&center_1.0 - &center_0.0).scalar_mul((t - time_0) / (time_1 - time_0)),
+                ),
+                None => center.clone(),
+            },
+            Quad { q, u, v, .. } => Point(&q.0 + &(u + v).scalar_mul(0.5)),
+            Plane { point, .. } => point.clone(),
+            Disk { center, .. } => center.clone(),
+            Implicit { bounds, .. } => Point(bounds.centroid()),
+            Csg(csg) => match csg.bounding_box(t, t) {
+                Some(bbox) => Point(bbox.centroid()),
+                None => Point(Vec3::iso(0.0)),
+            },
+            Heightfield(heightfield) => match heightfield.bounding_box(t, t) {
+                Some(bbox) => Point(bbox.centroid()),
+                None => Point(Vec3::iso(0.0)),
+            },
+            Bvh(bvh) => Point(bvh.bbox().centroid()),
+            Instance(instance) => Point(
+                instance
+                    .transform
+                    .transform_point(&instance.object.center_at(t).0),
+            ),
+            AnimatedInstance(instance) => Point(
+                instance
+                    .motion
+                    .at(t)
+                    .matrix()
+                    .transform_point(&instance.object.center_at(t).0),
+            ),
+            Custom(hittable) => match hittable.bounding_box(t, t) {
+                Some(bbox) => Point(bbox.centroid()),
+                None => Point(Vec3::iso(0.0)),
+            },
+        }
+    }
+
+    fn area(&self) -> f32 {
+        match self {
+            Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            Quad { u, v, .. } => u.cross(v).length(),
+            Disk { radius, .. } => PI * radius * radius,
+            _ => INFINITY,
+        }
+    }
+
+    // point on the surface picked uniformly by area, for lights
+    fn random_point(&self, r: &mut Random) -> Option<Vec3> {
+        match self {
+            Quad { q, u, v, .. } => {
+                Some(&q.0 + &(u.scalar_mul(r.random_double()) + v.scalar_mul(r.random_double())))
+            }
+            Disk {
+                center,
+                normal,
+                radius,
+                ..
+            } => {
+                let uvw = Onb::from_w(normal);
+                let rho = radius * r.random_double().sqrt();
+                let phi = 2.0 * PI * r.random_double();
+                Some(&center.0 + &uvw.local(&Vec3::new(rho * phi.cos(), rho * phi.sin(), 0.0)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// distance along the ray to the plane through `point`, `None` if parallel
+fn plane_hit_t(ray: &Ray, point: &Vec3, normal: &Vec3) -> Option<f32> {
+    let denom = normal.dot(&ray.direction.0);
+    if denom.abs() < 1e-8 {
+        None
+    } else {
+        Some(normal.dot(&(point - &ray.origin.0)) / denom)
+    }
+}
+
+impl Hittable for Object {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         match self {
             Sphere {
                 radius, material, ..
@@ -47,30 +381,379 @@ impl Object {
                     let t = root;
                     let p = ray.at(t);
                     let normal = Point((&p.0 - &self.center_at(ray.time).0).scalar_div(*radius));
-                    Some(HitRecord::new(p, t, normal, &material, &ray))
+                    let u = ((-normal.z).atan2(normal.x) + PI) / (2.0 * PI);
+                    let v = (-normal.y).clamp(-1.0, 1.0).acos() / PI;
+                    let dp_du = Vec3::new(normal.z, 0.0, -normal.x);
+                    Some(HitRecord::new(p, t, normal, (u, v), material, ray).with_tangent(&dp_du))
+                }
+            }
+            Quad { q, u, v, material } => {
+                let n = u.cross(v);
+                let t = plane_hit_t(ray, &q.0, &n)?;
+                if t < t_min || t > t_max {
+                    return None;
+                }
+                let p = ray.at(t);
+                let planar = &p.0 - &q.0;
+                let w = n.scalar_div(n.length_squared());
+                let alpha = w.dot(&planar.cross(v));
+                let beta = w.dot(&u.cross(&planar));
+                if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+                    return None;
+                }
+                Some(
+                    HitRecord::new(p, t, Point(n.unit_norm()), (alpha, beta), material, ray)
+                        .with_tangent(u),
+                )
+            }
+            Plane {
+                point,
+                normal,
+                material,
+            } => {
+                let t = plane_hit_t(ray, &point.0, normal)?;
+                if t < t_min || t > t_max {
+                    return None;
                 }
+                let p = ray.at(t);
+                let frame = Onb::from_w(normal);
+                let local = frame.to_local(&(&p.0 - &point.0));
+                Some(
+                    HitRecord::new(
+                        p,
+                        t,
+                        Point(normal.clone()),
+                        (local.x, local.y),
+                        material,
+                        ray,
+                    )
+                    .with_tangent(&frame.u),
+                )
             }
+            Disk {
+                center,
+                normal,
+                radius,
+                material,
+            } => {
+                let t = plane_hit_t(ray, &center.0, normal)?;
+                if t < t_min || t > t_max {
+                    return None;
+                }
+                let p = ray.at(t);
+                let frame = Onb::from_w(normal);
+                let local = frame.to_local(&(&p.0 - &center.0));
+                let rho = (local.x * local.x + local.y * local.y).sqrt();
+                if rho > *radius {
+                    return None;
+                }
+                let u = (local.y.atan2(local.x) + PI) / (2.0 * PI);
+                let dp_du = frame.local(&Vec3::new(-local.y, local.x, 0.0));
+                Some(
+                    HitRecord::new(
+                        p,
+                        t,
+                        Point(normal.clone()),
+                        (u, rho / radius),
+                        material,
+                        ray,
+                    )
+                    .with_tangent(&dp_du),
+                )
+            }
+            Analytic {
+                shape,
+                sweep,
+                material,
+                ..
+            } => {
+                let center = self.center_at(ray.time);
+                let origin = &ray.origin.0 - &center.0;
+                let hit = shape.hit(
+                    &origin,
+                    &ray.direction.0,
+                    t_min,
+                    t_max,
+                    degrees_to_radians(*sweep),
+                )?;
+                Some(
+                    HitRecord::new(
+                        ray.at(hit.t),
+                        hit.t,
+                        Point(hit.outward_normal),
+                        hit.uv,
+                        material,
+                        ray,
+                    )
+                    .with_tangent(&hit.dp_du),
+                )
+            }
+            Implicit {
+                sdf,
+                bounds,
+                material,
+            } => {
+                let t = sdf.trace(ray, t_min, t_max, bounds)?;
+                let p = ray.at(t);
+                let normal = sdf.normal(&p.0);
+                // spherical mapping of the normal, like spheres
+                let u = ((-normal.z).atan2(normal.x) + PI) / (2.0 * PI);
+                let v = (-normal.y).clamp(-1.0, 1.0).acos() / PI;
+                let dp_du = Vec3::new(normal.z, 0.0, -normal.x);
+                Some(
+                    HitRecord::new(p, t, Point(normal), (u, v), material, ray).with_tangent(&dp_du),
+                )
+            }
+            Csg(csg) => csg.hit(ray, t_min, t_max),
+            Heightfield(heightfield) => heightfield.hit(ray, t_min, t_max),
+            Bvh(bvh) => bvh.hit(ray, t_min, t_max),
+            Instance(instance) => instance.hit(ray, t_min, t_max),
+            AnimatedInstance(instance) => instance.hit(ray, t_min, t_max),
+            Custom(hittable) => hittable.hit(ray, t_min, t_max),
         }
     }
 
-    pub fn center_at(&self, t: f32) -> Point {
+    fn bounding_box(&self, time_0: f32, time_1: f32) -> Option<Aabb> {
         match self {
-            Sphere {
-                moving_component,
+            Sphere { radius, .. } => {
+                let r = Vec3::iso(radius.abs());
+                let box_at = |t| {
+                    let c = self.center_at(t);
+                    Aabb::new(Point(&c.0 - &r), Point(&c.0 + &r))
+                };
+                Some(box_at(time_0).surrounding(&box_at(time_1)))
+            }
+            Quad { q, u, v, .. } => {
+                let diagonal = &(&q.0 + u) + v;
+                let a = Aabb::new(Point(q.0.min(&diagonal)), Point(q.0.max(&diagonal)));
+                let b_0 = &q.0 + u;
+                let b_1 = &q.0 + v;
+                let b = Aabb::new(Point(b_0.min(&b_1)), Point(b_0.max(&b_1)));
+                Some(a.surrounding(&b).pad(1e-4))
+            }
+            Plane { .. } => None,
+            Disk {
                 center,
+                normal,
+                radius,
                 ..
-            } => match moving_component {
-                Some(MovingComponent {
-                    center_0,
-                    center_1,
-                    time_0,
-                    time_1,
-                }) => Point(
-                    &center_0.0
-                        + &(&center_1.0 - &center_0.0).scalar_mul((t - time_0) / (time_1 - time_0)),
-                ),
-                None => center.clone(),
-            },
+            } => {
+                let extent = Vec3::new(
+                    (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+                    (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+                    (1.0 - normal.z * normal.z).max(0.0).sqrt(),
+                )
+                .scalar_mul(*radius);
+                Some(Aabb::new(Point(&center.0 - &extent), Point(&center.0 + &extent)).pad(1e-4))
+            }
+            Analytic { shape, .. } => {
+                let bounds = shape.bounds();
+                let box_at = |t| {
+                    let c = self.center_at(t);
+                    Aabb::new(Point(&bounds.min.0 + &c.0), Point(&bounds.max.0 + &c.0))
+                };
+                Some(box_at(time_0).surrounding(&box_at(time_1)))
+            }
+            Implicit { bounds, .. } => Some(bounds.clone()),
+            Csg(csg) => csg.bounding_box(time_0, time_1),
+            Heightfield(heightfield) => heightfield.bounding_box(time_0, time_1),
+            Bvh(bvh) => Some(bvh.bbox().clone()),
+            Instance(instance) => instance.bounding_box(time_0, time_1),
+            AnimatedInstance(instance) => instance.bounding_box(time_0, time_1),
+            Custom(hittable) => hittable.bounding_box(time_0, time_1),
+        }
+    }
+
+    fn pdf_value(&self, origin: &Point, direction: &Vec3, time: f32) -> f32 {
+        match self {
+            Sphere { radius, .. } => {
+                let ray = Ray::new(origin.clone(), Point(direction.clone()), time);
+                if self.hit(&ray, 0.001, INFINITY).is_none() {
+                    return 0.0;
+                }
+                let distance_squared = (&self.center_at(time).0 - &origin.0).length_squared();
+                let cos_theta_max = (1.0 - radius * radius / distance_squared).max(0.0).sqrt();
+                let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+                1.0 / solid_angle
+            }
+            Quad { .. } | Disk { .. } => {
+                let ray = Ray::new(origin.clone(), Point(direction.clone()), time);
+                match self.hit(&ray, 0.001, INFINITY) {
+                    Some(rec) => {
+                        let distance_squared = rec.t * rec.t * direction.length_squared();
+                        let cosine = (direction.dot(&rec.normal.0) / direction.length()).abs();
+                        // grazing directions are never sampled
+                        if cosine < 1e-6 {
+                            return 0.0;
+                        }
+                        distance_squared / (cosine * self.area())
+                    }
+                    None => 0.0,
+                }
+            }
+            Plane { .. } | Analytic { .. } | Implicit { .. } | Csg(_) | Heightfield(_) => 0.0,
+            Bvh(bvh) => bvh.pdf_value(origin, direction, time),
+            Instance(instance) => instance.pdf_value(origin, direction, time),
+            AnimatedInstance(instance) => instance.pdf_value(origin, direction, time),
+            Custom(hittable) => hittable.pdf_value(origin, direction, time),
+        }
+    }
+
+    fn random_direction(&self, origin: &Point, time: f32, r: &mut Random) -> Option<Vec3> {
+        match self {
+            Sphere { radius, .. } => {
+                let direction = &self.center_at(time).0 - &origin.0;
+                let distance_squared = direction.length_squared();
+                if distance_squared <= radius * radius {
+                    return None;
+                }
+                let uvw = Onb::from_w(&direction);
+                Some(uvw.local(&Vec3::random_to_sphere(r, *radius, distance_squared)))
+            }
+            Quad { .. } | Disk { .. } => self.random_point(r).map(|p| &p - &origin.0),
+            Plane { .. } | Analytic { .. } | Implicit { .. } | Csg(_) | Heightfield(_) => None,
+            Bvh(bvh) => bvh.random_direction(origin, time, r),
+            Instance(instance) => instance.random_direction(origin, time, r),
+            AnimatedInstance(instance) => instance.random_direction(origin, time, r),
+            Custom(hittable) => hittable.random_direction(origin, time, r),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracing::color::Color;
+
+    fn gray() -> Material {
+        Material::new_lambertian(Color::new_rgb(0.5, 0.5, 0.5))
+    }
+
+    fn down_from(x: f32, z: f32) -> Ray {
+        Ray::new(
+            Point(Vec3::new(x, 2.0, z)),
+            Point(Vec3::new(0.0, -1.0, 0.0)),
+            0.0,
+        )
+    }
+
+    #[test]
+    fn test_flat_hits() {
+        let quad = Object::new_xz_rect(-1.0, 1.0, 0.0, 2.0, 0.5, gray());
+        let rec = quad.hit(&down_from(0.5, 1.5), 0.001, INFINITY).unwrap();
+        assert!((rec.t - 1.5).abs() < 1e-6);
+        assert_eq!(rec.normal.0, Vec3::new(0.0, 1.0, 0.0));
+        assert!(rec.front_face);
+        assert!((rec.u - 0.75).abs() < 1e-6 && (rec.v - 0.75).abs() < 1e-6);
+        assert!(quad.hit(&down_from(1.5, 1.0), 0.001, INFINITY).is_none());
+        assert!(quad.hit(&down_from(0.5, 1.5), 0.001, 1.0).is_none());
+
+        let disk = Object::new_disk(Point(Vec3::iso(0.0)), Vec3::new(0.0, 2.0, 0.0), 1.0, gray());
+        let rec = disk.hit(&down_from(0.6, 0.0), 0.001, INFINITY).unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-6 && (rec.v - 0.6).abs() < 1e-6);
+        assert!(disk.hit(&down_from(0.8, 0.8), 0.001, INFINITY).is_none());
+
+        let plane = Object::new_plane(Point(Vec3::iso(0.0)), Vec3::new(0.0, 1.0, 0.0), gray());
+        let rec = plane
+            .hit(&down_from(100.0, -50.0), 0.001, INFINITY)
+            .unwrap();
+        assert!((rec.t - 2.0).abs() < 1e-6);
+        // from below, the normal still faces the ray
+        let up = Ray::new(
+            Point(Vec3::new(0.0, -1.0, 0.0)),
+            Point(Vec3::new(0.0, 1.0, 0.0)),
+            0.0,
+        );
+        let rec = plane.hit(&up, 0.001, INFINITY).unwrap();
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal.0, Vec3::new(0.0, -1.0, 0.0));
+        let parallel = Ray::new(
+            Point(Vec3::new(0.0, 1.0, 0.0)),
+            Point(Vec3::new(1.0, 0.0, 0.0)),
+            0.0,
+        );
+        assert!(plane.hit(&parallel, 0.001, INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_flat_bounding_boxes() {
+        let quad = Object::new_quad(
+            Point(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(-1.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            gray(),
+        );
+        let bbox = quad.bounding_box(0.0, 1.0).unwrap();
+        assert!((&bbox.min.0 - &Vec3::new(0.0, 0.0, 0.0)).length() < 1e-3);
+        assert!((&bbox.max.0 - &Vec3::new(1.0, 1.0, 2.0)).length() < 1e-3);
+
+        let disk = Object::new_disk(
+            Point(Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            gray(),
+        );
+        let bbox = disk.bounding_box(0.0, 1.0).unwrap();
+        assert!((&bbox.min.0 - &Vec3::new(-2.0, 1.0, -2.0)).length() < 1e-3);
+        assert!((&bbox.max.0 - &Vec3::new(2.0, 1.0, 2.0)).length() < 1e-3);
+        // flat boxes are padded to be hit
+        assert!(bbox.max.y > bbox.min.y);
+
+        let plane = Object::new_plane(Point(Vec3::iso(0.0)), Vec3::new(0.0, 1.0, 0.0), gray());
+        assert!(plane.bounding_box(0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_grazing_light_pdf() {
+        let quad = Object::new_xz_rect(-1.0, 1.0, -1.0, 1.0, 0.0, gray());
+        let disk = Object::new_disk(Point(Vec3::iso(0.0)), Vec3::new(0.0, 1.0, 0.0), 1.0, gray());
+        let origin = Point(Vec3::new(-1.0, 1e-7, 0.0));
+        let direction = Vec3::new(1.0, -1e-7, 0.0);
+        for light in [quad, disk] {
+            assert_eq!(light.pdf_value(&origin, &direction, 0.0), 0.0);
+            let pdf = light.pdf_value(&Point(Vec3::new(0.0, 1.0, 0.0)), &direction, 0.0);
+            assert_eq!(pdf, 0.0);
+            let straight = light.pdf_value(
+                &Point(Vec3::new(0.0, 1.0, 0.0)),
+                &Vec3::new(0.0, -1.0, 0.0),
+                0.0,
+            );
+            assert!((straight - 1.0 / light.area()).abs() < 1e-4);
         }
     }
+
+    #[test]
+    fn test_moving_analytic() {
+        let torus = AnalyticShape::Torus {
+            major_radius: 1.0,
+            minor_radius: 0.25,
+        };
+        let moving = Object::new_moving_analytic(
+            torus,
+            360.0,
+            gray(),
+            MovingComponent {
+                center_0: Point(Vec3::iso(0.0)),
+                center_1: Point(Vec3::new(2.0, 0.0, 0.0)),
+                time_0: 0.0,
+                time_1: 1.0,
+            },
+        );
+        // down through the tube, which is under x = 1 at the start and
+        // x = 3 at the end
+        let at = |x: f32, time: f32| {
+            let ray = Ray::new(
+                Point(Vec3::new(x, 2.0, 0.0)),
+                Point(Vec3::new(0.0, -1.0, 0.0)),
+                time,
+            );
+            moving.hit(&ray, 0.001, INFINITY).map(|rec| rec.t)
+        };
+        assert!((at(1.0, 0.0).unwrap() - 1.75).abs() < 1e-4);
+        assert!(at(3.0, 0.0).is_none());
+        assert!((at(3.0, 1.0).unwrap() - 1.75).abs() < 1e-4);
+        assert!((at(2.0, 0.5).unwrap() - 1.75).abs() < 1e-4);
+        let bbox = moving.bounding_box(0.0, 1.0).unwrap();
+        assert!(bbox.min.x <= -1.25 && bbox.max.x >= 3.25);
+    }
 }
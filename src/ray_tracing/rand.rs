@@ -1,14 +1,20 @@
+use rand::rngs::SmallRng;
 use rand::*;
 
-pub struct Random(rngs::ThreadRng);
+pub struct Random(SmallRng);
 
 impl Default for Random {
     fn default() -> Random {
-        Random(rngs::ThreadRng::default())
+        Random(SmallRng::from_entropy())
     }
 }
 
 impl Random {
+    /// Same sequence on every run, for tests.
+    pub fn seeded(seed: u64) -> Random {
+        Random(SmallRng::seed_from_u64(seed))
+    }
+
     pub fn random_double(&mut self) -> f32 {
         self.0.gen()
     }
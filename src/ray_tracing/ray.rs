@@ -1,48 +1,188 @@
-use crate::Object;
-
+use super::bvh::Bvh;
 use super::color::*;
 use super::geom::*;
 use super::material::*;
+use super::medium::*;
+use super::object::*;
 use super::rand::*;
+use super::spectrum::*;
 
 #[derive(PartialEq, Debug, Clone)]
-pub struct Ray<'a> {
-    pub origin: &'a Point,
+pub struct Ray {
+    pub origin: Point,
     pub direction: Point,
     pub time: f32,
+    /// Set on spectral paths, colours along them are spectral values.
+    pub wavelengths: Option<Wavelengths>,
 }
 
-impl<'a> Ray<'a> {
-    pub fn new(origin: &'a Point, direction: Point, time: f32) -> Ray {
+impl Ray {
+    pub fn new(origin: Point, direction: Point, time: f32) -> Ray {
         Ray {
             origin,
             direction,
             time,
+            wavelengths: None,
         }
     }
 
+    pub fn with_wavelengths(mut self, wavelengths: Option<Wavelengths>) -> Ray {
+        self.wavelengths = wavelengths;
+        self
+    }
+
     pub fn at(&self, t: f32) -> Point {
         Point(&self.origin.0 + &self.direction.0.scalar_mul(t))
     }
 
-    pub fn color(&self, world: &HittableList, depth: u32, r: &mut Random) -> Color {
-        if depth == 0 {
-            Color::zero()
-        } else if let Some(rec) = world.hit(&self, 0.001, INFINITY) {
-            match rec.material.scatter(self, &rec, r) {
-                Some((color, ray_out)) => Color::new(
-                    ray_out
-                        .color(world, depth - 1, r)
-                        .rgb
-                        .index_wise_mul(&color.rgb),
-                ),
-                None => Color::zero(),
+    /// Radiance arriving along the ray, estimated with a single path.
+    pub fn color(&self, world: &HittableList, integrator: &Integrator, r: &mut Random) -> Color {
+        if integrator.spectral && self.wavelengths.is_none() {
+            let wavelengths = Wavelengths::sample(r);
+            let ray = self.clone().with_wavelengths(Some(wavelengths));
+            return wavelengths.to_rgb(&ray.color(world, integrator, r));
+        }
+        let spectral = |color: Color| upsample(self.wavelengths.as_ref(), color);
+        let mut ray = self.clone();
+        let mut radiance = Color::zero();
+        let mut throughput = Color::new_rgb(1.0, 1.0, 1.0);
+        let mut bounces = Bounces::default();
+        // density the last bounce sampled `ray` with, `None` for camera rays
+        // and after specular bounces where light sampling could not have
+        // found the same path.
+        let mut bsdf_pdf: Option<f32> = None;
+        // where the last bounce happened, rays keep going through medium
+        // interfaces
+        let mut scatter_origin = ray.origin.clone();
+        // camera rays start outside of every bounded medium
+        let mut medium = world.fog.as_ref();
+        // whether `medium` is the inside of a subsurface material
+        let mut walking = false;
+        let mut crossings = 0;
+
+        loop {
+            let mut hit = world.hit(&ray, 0.001, INFINITY);
+            if let Some(current) = medium {
+                let length = ray.direction.0.length();
+                let max_distance = hit.as_ref().map_or(INFINITY, |rec| rec.t * length);
+                let flight = current.sample_flight(&ray, max_distance, r);
+                radiance += &throughput * &flight.emitted;
+                throughput = &throughput * &flight.weight;
+                if throughput.is_black() {
+                    break;
+                }
+                if let Some(distance) = flight.scatter {
+                    hit = Some(current.scattering_record(&ray, distance / length));
+                }
             }
-        } else {
-            let t = 0.5 * (self.direction.0.unit_norm().y + 1.0);
-            Color {
-                rgb: Ray::VEC_ISO_1.scalar_mul(1.0 - t) + Ray::VEC_COLOR.scalar_mul(t),
+            let rec = match hit {
+                Some(rec) => rec,
+                None => {
+                    radiance += &throughput * &spectral(world.background.color(&ray));
+                    break;
+                }
+            };
+            if let Material::MediumInterface(_) = rec.material {
+                // invisible boundary, only the medium changes
+                medium = world.medium_behind(&rec);
+                crossings += 1;
+                if crossings > Integrator::MAX_MEDIUM_CROSSINGS {
+                    break;
+                }
+                ray = Ray::new(rec.p, ray.direction, ray.time).with_wavelengths(ray.wavelengths);
+                continue;
+            }
+            if !rec.front_face {
+                // the segment ran inside the object
+                let distance = rec.t * ray.direction.0.length();
+                throughput = &throughput * &rec.material.transmittance(&rec, distance);
+            }
+
+            let emitted = spectral(rec.material.emitted(&rec));
+            if !emitted.is_black() {
+                let weight = match bsdf_pdf {
+                    Some(pdf) => power_heuristic(
+                        pdf,
+                        world.light_pdf(&scatter_origin, &ray.direction.0.unit_norm(), ray.time),
+                    ),
+                    None => 1.0,
+                };
+                radiance += (&throughput * &emitted).scalar_mul(weight);
+            }
+
+            let wo = -&ray.direction.0.unit_norm();
+            // shadow rays from inside a subsurface walk always end on its
+            // boundary
+            let in_walk = walking && rec.material.flags().contains(BsdfFlags::VOLUME);
+            if !rec.material.flags().is_delta() && !in_walk {
+                radiance += &throughput * &world.sample_light(&wo, &rec, ray.time, medium, r);
+            }
+
+            let sample = match rec.material.sample(&wo, &rec, r) {
+                Some(sample) => sample,
+                None => break,
+            };
+            // scattering and internal reflections until the walk leaves
+            let walk_step = walking && !sample.flags.contains(BsdfFlags::TRANSMISSION);
+            let within_limits = if walk_step {
+                bounces.add_walk_step(integrator)
+            } else {
+                bounces.add(sample.flags, integrator)
+            };
+            if !within_limits {
+                break;
+            }
+            throughput = &throughput * &spectral(sample.weight);
+            if sample.flags.contains(BsdfFlags::DISPERSION) && ray.wavelengths.is_some() {
+                // only the hero wavelength goes this way
+                throughput = Color::new_rgb(3.0 * throughput.rgb.x, 0.0, 0.0);
+            }
+            if throughput.is_black() {
+                break;
+            }
+            if !walk_step && bounces.total >= integrator.russian_roulette_depth {
+                let survival = throughput.max_component().min(0.95);
+                if r.random_double() >= survival {
+                    break;
+                }
+                throughput = throughput.scalar_mul(1.0 / survival);
+            }
+
+            bsdf_pdf = if sample.flags.contains(BsdfFlags::SPECULAR) {
+                None
+            } else {
+                Some(sample.pdf)
+            };
+            if sample.flags.contains(BsdfFlags::TRANSMISSION) && rec.material.interior().is_some() {
+                medium = world.medium_behind(&rec);
+                walking = rec.front_face && rec.material.is_subsurface();
+            }
+            scatter_origin = rec.p.clone();
+            ray = Ray::new(rec.p, Point(sample.direction), ray.time)
+                .with_wavelengths(ray.wavelengths);
+        }
+        radiance
+    }
+}
+
+/// Radiance of rays escaping the scene.
+pub enum Background {
+    /// White to blue vertical gradient.
+    Sky,
+    Color(Color),
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Sky => {
+                let t = 0.5 * (ray.direction.0.unit_norm().y + 1.0);
+                Color {
+                    rgb: Background::VEC_ISO_1.scalar_mul(1.0 - t)
+                        + Background::VEC_COLOR.scalar_mul(t),
+                }
             }
+            Background::Color(color) => color.clone(),
         }
     }
 
@@ -50,12 +190,96 @@ impl<'a> Ray<'a> {
     const VEC_ISO_1: Vec3 = Vec3::new(1.0, 1.0, 1.0);
 }
 
+/// Bounce limits of the path tracer.
+pub struct Integrator {
+    pub max_depth: u32,
+    pub max_diffuse_depth: u32,
+    pub max_glossy_depth: u32,
+    pub max_transmission_depth: u32,
+    /// Scattering events in participating media.
+    pub max_volume_depth: u32,
+    /// Scattering events and internal reflections of a random walk under a
+    /// subsurface boundary, counted apart from the bounces above and never
+    /// ended by Russian roulette.
+    pub max_subsurface_depth: u32,
+    /// Bounces after which paths are terminated with Russian roulette
+    /// according to their throughput.
+    pub russian_roulette_depth: u32,
+    /// Trace wavelengths instead of RGB, for dispersion.
+    pub spectral: bool,
+}
+
+impl Integrator {
+    // medium boundaries a path may go through, they are not bounces
+    const MAX_MEDIUM_CROSSINGS: u32 = 256;
+}
+
+impl Default for Integrator {
+    fn default() -> Integrator {
+        Integrator {
+            max_depth: 50,
+            max_diffuse_depth: 8,
+            max_glossy_depth: 16,
+            max_transmission_depth: 32,
+            max_volume_depth: 32,
+            max_subsurface_depth: 1024,
+            russian_roulette_depth: 3,
+            spectral: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Bounces {
+    total: u32,
+    diffuse: u32,
+    glossy: u32,
+    transmission: u32,
+    volume: u32,
+    subsurface: u32,
+}
+
+impl Bounces {
+    // counts a bounce on the sampled lobe, false once a limit is exceeded
+    fn add(&mut self, lobe: BsdfFlags, integrator: &Integrator) -> bool {
+        self.total += 1;
+        let within_lobe_limit = if lobe.contains(BsdfFlags::VOLUME) {
+            self.volume += 1;
+            self.volume <= integrator.max_volume_depth
+        } else if lobe.contains(BsdfFlags::TRANSMISSION) {
+            self.transmission += 1;
+            self.transmission <= integrator.max_transmission_depth
+        } else if lobe.contains(BsdfFlags::DIFFUSE) {
+            self.diffuse += 1;
+            self.diffuse <= integrator.max_diffuse_depth
+        } else {
+            self.glossy += 1;
+            self.glossy <= integrator.max_glossy_depth
+        };
+        within_lobe_limit && self.total <= integrator.max_depth
+    }
+
+    // counts a step of a subsurface walk, which is not a bounce
+    fn add_walk_step(&mut self, integrator: &Integrator) -> bool {
+        self.subsurface += 1;
+        self.subsurface <= integrator.max_subsurface_depth
+    }
+}
+
+#[derive(Clone)]
 pub struct HitRecord<'a> {
     pub p: Point,
     pub normal: Point,
+    /// Unit direction of increasing `u`, orthogonal to `normal`.
+    pub tangent: Vec3,
     pub material: &'a Material,
     pub t: f32,
+    /// Surface coordinates, both in `[0, 1]` for bounded surfaces.
+    pub u: f32,
+    pub v: f32,
     pub front_face: bool,
+    /// Those of the ray, for materials that depend on the wavelength.
+    pub wavelengths: Option<Wavelengths>,
 }
 
 impl<'a> HitRecord<'a> {
@@ -63,6 +287,7 @@ impl<'a> HitRecord<'a> {
         p: Point,
         t: f32,
         outward_normal: Point,
+        (u, v): (f32, f32),
         material: &'a Material,
         ray: &Ray,
     ) -> HitRecord<'a> {
@@ -72,12 +297,42 @@ impl<'a> HitRecord<'a> {
         } else {
             Point(-&outward_normal.0)
         };
+        let tangent = Onb::from_w(&normal.0).u;
         HitRecord {
             p,
             normal,
+            tangent,
             t,
             material,
+            u,
+            v,
             front_face,
+            wavelengths: ray.wavelengths,
+        }
+    }
+
+    /// Sets the tangent from the surface derivative `dp/du`, keeping the
+    /// current one where it is degenerate like at the poles of a sphere.
+    pub fn with_tangent(mut self, dp_du: &Vec3) -> HitRecord<'a> {
+        let normal = &self.normal.0;
+        let tangent = dp_du - &normal.scalar_mul(normal.dot(dp_du));
+        if tangent.length_squared() > 1e-12 {
+            self.tangent = tangent.unit_norm();
+        } else if self.tangent.dot(normal).abs() > 1e-3 {
+            self.tangent = Onb::from_w(normal).u;
+        }
+        self
+    }
+
+    /// Frame with `w` along the normal and `u` along the tangent, where
+    /// materials are evaluated.
+    pub fn shading_frame(&self) -> Onb {
+        let w = self.normal.0.clone();
+        let v = w.cross(&self.tangent);
+        Onb {
+            u: self.tangent.clone(),
+            v,
+            w,
         }
     }
 
@@ -89,22 +344,56 @@ impl<'a> HitRecord<'a> {
 
 pub struct HittableList {
     pub hittables: Vec<Object>,
+    /// Emitters that are also sampled explicitly at every diffuse or glossy
+    /// bounce.
+    pub lights: Vec<Object>,
+    pub background: Background,
+    /// Medium filling the scene outside of bounded media.
+    pub fog: Option<Medium>,
+}
+
+impl Default for HittableList {
+    fn default() -> HittableList {
+        HittableList::new()
+    }
 }
 
 impl HittableList {
     pub fn new() -> HittableList {
         HittableList {
             hittables: Vec::with_capacity(64),
+            lights: Vec::new(),
+            background: Background::Sky,
+            fog: None,
         }
     }
     pub fn add(&mut self, hittable: Object) {
         self.hittables.push(hittable);
     }
 
-    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    /// Adds an object with an emissive material that supports
+    /// `Hittable::random_direction`, so that it is sampled directly.
+    pub fn add_light(&mut self, light: Object) {
+        self.lights.push(light);
+    }
+
+    /// Moves every bounded object into a single BVH, unbounded ones (and
+    /// custom hittables without a box) are still tested one by one.
+    pub fn build_bvh(&mut self, time_0: f32, time_1: f32) {
+        let (bounded, mut unbounded): (Vec<Object>, Vec<Object>) = self
+            .hittables
+            .drain(..)
+            .partition(|object| object.bounding_box(time_0, time_1).is_some());
+        if let Some(bvh) = Bvh::new(bounded, time_0, time_1) {
+            unbounded.push(Object::Bvh(Box::new(bvh)));
+        }
+        self.hittables = unbounded;
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let mut temp_rec: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
-        for object in &self.hittables {
+        for object in self.hittables.iter().chain(self.lights.iter()) {
             // note closest_so_far is used as t_max
             if let Some(rec) = object.hit(ray, t_min, closest_so_far) {
                 closest_so_far = rec.t;
@@ -113,4 +402,264 @@ impl HittableList {
         }
         temp_rec
     }
+
+    /// Density of choosing `direction` from `origin` when sampling lights.
+    pub fn light_pdf(&self, origin: &Point, direction: &Vec3, time: f32) -> f32 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_value(origin, direction, time))
+            .sum();
+        sum / self.lights.len() as f32
+    }
+
+    // medium on the other side of a surface with an interior, entered on
+    // front faces and left for the fog on back faces
+    fn medium_behind<'a>(&'a self, rec: &HitRecord<'a>) -> Option<&'a Medium> {
+        if rec.front_face {
+            rec.material.interior()
+        } else {
+            self.fog.as_ref()
+        }
+    }
+
+    // emission seen from `rec` along `wi`, attenuated by the media on the
+    // way, `None` if the direction does not reach an emitter. Spectral
+    // like the transmittance when `rec` carries wavelengths
+    fn unoccluded_emission<'a>(
+        &'a self,
+        rec: &HitRecord,
+        wi: &Vec3,
+        time: f32,
+        mut medium: Option<&'a Medium>,
+        r: &mut Random,
+    ) -> Option<Color> {
+        let mut ray =
+            Ray::new(rec.p.clone(), Point(wi.clone()), time).with_wavelengths(rec.wavelengths);
+        let mut transmittance = Color::new_rgb(1.0, 1.0, 1.0);
+        for _ in 0..Integrator::MAX_MEDIUM_CROSSINGS {
+            let hit = self.hit(&ray, 0.001, INFINITY)?;
+            if let Some(current) = medium {
+                let distance = hit.t * ray.direction.0.length();
+                let through = current.transmittance(&ray, distance, r);
+                transmittance = &transmittance * &through;
+            }
+            match hit.material {
+                Material::MediumInterface(_) => {
+                    medium = self.medium_behind(&hit);
+                    ray = Ray::new(hit.p, ray.direction, time).with_wavelengths(rec.wavelengths);
+                }
+                material => {
+                    let emitted = upsample(rec.wavelengths.as_ref(), material.emitted(&hit));
+                    return Some(&transmittance * &emitted);
+                }
+            }
+        }
+        None
+    }
+
+    /// Next event estimation: radiance reaching `rec` from a randomly chosen
+    /// light, weighted against BSDF sampling with the power heuristic.
+    pub fn sample_light(
+        &self,
+        wo: &Vec3,
+        rec: &HitRecord,
+        time: f32,
+        medium: Option<&Medium>,
+        r: &mut Random,
+    ) -> Color {
+        if self.lights.is_empty() {
+            return Color::zero();
+        }
+        let index =
+            ((r.random_double() * self.lights.len() as f32) as usize).min(self.lights.len() - 1);
+        let wi = match self.lights[index].random_direction(&rec.p, time, r) {
+            Some(direction) => direction.unit_norm(),
+            None => return Color::zero(),
+        };
+        let spectral = |color: Color| upsample(rec.wavelengths.as_ref(), color);
+        let f = spectral(rec.material.eval(wo, &wi, rec));
+        if f.is_black() {
+            return Color::zero();
+        }
+        let light_pdf = self.light_pdf(&rec.p, &wi, time);
+        if light_pdf <= 0.0 {
+            return Color::zero();
+        }
+        // through a surface with an interior the shadow ray starts in the
+        // medium behind it
+        let medium = if wi.dot(&rec.normal.0) < 0.0 && rec.material.interior().is_some() {
+            self.medium_behind(rec)
+        } else {
+            medium
+        };
+        let emitted = match self.unoccluded_emission(rec, &wi, time, medium, r) {
+            Some(emitted) => emitted,
+            None => return Color::zero(),
+        };
+        let weight = power_heuristic(light_pdf, rec.material.pdf(wo, &wi, rec));
+        (&f * &emitted).scalar_mul(weight / light_pdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spectral_light_sampling_through_fog() {
+        // a floor lit by a quad through strongly coloured absorbing fog
+        let mut r = Random::seeded(1);
+        let mut world = HittableList::new();
+        world.background = Background::Color(Color::zero());
+        world.fog = Some(Medium::new(
+            0.5,
+            Color::new_rgb(0.1, 1.0, 2.0),
+            Color::zero(),
+            0.0,
+        ));
+        let gray = Material::new_lambertian(Color::new_rgb(0.8, 0.8, 0.8));
+        world.add(Object::new_xz_rect(-5.0, 5.0, -5.0, 5.0, 0.0, gray));
+        world.add_light(Object::new_quad(
+            Point(Vec3::new(-0.5, 2.0, -0.5)),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Material::new_diffuse_light(Color::new_rgb(4.0, 4.0, 4.0)),
+        ));
+        let wavelengths = Some(Wavelengths::sample(&mut r));
+        let ray = Ray::new(
+            Point(Vec3::new(0.0, 1.0, 0.0)),
+            Point(Vec3::new(0.0, -1.0, 0.0)),
+            0.0,
+        )
+        .with_wavelengths(wavelengths);
+        let rec = world.hit(&ray, 0.001, INFINITY).unwrap();
+        let wo = -&ray.direction.0;
+        let fog = world.fog.as_ref();
+
+        // light sampling and the rest of the MIS pair add up to plain
+        // BSDF sampling
+        let n = 400_000;
+        let (mut nee, mut bsdf_mis, mut bsdf) = (Color::zero(), Color::zero(), Color::zero());
+        for _ in 0..n {
+            nee += world.sample_light(&wo, &rec, 0.0, fog, &mut r);
+            let sample = rec.material.sample(&wo, &rec, &mut r).unwrap();
+            let shadow = Ray::new(rec.p.clone(), Point(sample.direction.clone()), 0.0)
+                .with_wavelengths(wavelengths);
+            let light = match world.hit(&shadow, 0.001, INFINITY) {
+                Some(light) => light,
+                None => continue,
+            };
+            let distance = light.t * shadow.direction.0.length();
+            let through = fog.unwrap().transmittance(&shadow, distance, &mut r);
+            let emitted = upsample(wavelengths.as_ref(), light.material.emitted(&light));
+            let f = &upsample(wavelengths.as_ref(), sample.weight) * &through;
+            let contribution = &f * &emitted;
+            let light_pdf = world.light_pdf(&rec.p, &sample.direction.unit_norm(), 0.0);
+            bsdf_mis += contribution.scalar_mul(power_heuristic(sample.pdf, light_pdf));
+            bsdf += contribution;
+        }
+        let total = (nee + bsdf_mis).scalar_mul(1.0 / n as f32);
+        let expected = bsdf.scalar_mul(1.0 / n as f32);
+        for (a, b) in [
+            (total.rgb.x, expected.rgb.x),
+            (total.rgb.y, expected.rgb.y),
+            (total.rgb.z, expected.rgb.z),
+        ] {
+            assert!(
+                (a - b).abs() < 0.05 * b + 1e-3,
+                "{:?} {:?}",
+                total,
+                expected
+            );
+        }
+    }
+
+    // average radiance of rays aimed at a unit sphere at the origin
+    fn furnace(world: &HittableList, integrator: &Integrator, n: usize) -> Color {
+        let mut r = Random::seeded(1);
+        let mut sum = Color::zero();
+        for _ in 0..n {
+            let target = Vec3::random_in_unit_sphere(&mut r).scalar_mul(0.99);
+            let origin = Vec3::new(0.0, 0.0, 4.0);
+            let ray = Ray::new(Point(origin.clone()), Point(&target - &origin), 0.0);
+            sum += ray.color(world, integrator, &mut r);
+        }
+        sum.scalar_mul(1.0 / n as f32)
+    }
+
+    #[test]
+    fn test_subsurface_furnace() {
+        // nothing is absorbed, so light only goes in and out again however
+        // long the walk takes
+        let mut world = HittableList::new();
+        world.background = Background::Color(Color::new_rgb(1.0, 1.0, 1.0));
+        world.add(Object::Sphere {
+            center: Point(Vec3::iso(0.0)),
+            radius: 1.0,
+            material: Material::new_subsurface(
+                Color::new_rgb(1.0, 1.0, 1.0),
+                Color::new_rgb(0.1, 0.1, 0.1),
+                1.33,
+                0.0,
+            ),
+            moving_component: None,
+        });
+        let average = furnace(&world, &Integrator::default(), 20_000);
+        assert!((average.rgb.x - 1.0).abs() < 0.03, "{:?}", average);
+    }
+
+    #[test]
+    fn test_bounce_limits() {
+        let integrator = Integrator {
+            max_depth: 6,
+            max_diffuse_depth: 2,
+            max_glossy_depth: 3,
+            max_transmission_depth: 4,
+            max_volume_depth: 5,
+            ..Integrator::default()
+        };
+        let lobes = [
+            (BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION, 2),
+            (BsdfFlags::GLOSSY | BsdfFlags::REFLECTION, 3),
+            (BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION, 4),
+            (BsdfFlags::VOLUME, 5),
+        ];
+        for (lobe, limit) in lobes {
+            let mut bounces = Bounces::default();
+            for _ in 0..limit {
+                assert!(bounces.add(lobe, &integrator));
+            }
+            assert!(!bounces.add(lobe, &integrator));
+        }
+        // each lobe within its limit, but too many in total
+        let mut bounces = Bounces::default();
+        for (lobe, _) in lobes.iter().chain(&lobes[..2]) {
+            assert!(bounces.add(*lobe, &integrator));
+        }
+        assert!(!bounces.add(lobes[2].0, &integrator));
+    }
+
+    #[test]
+    fn test_white_furnace() {
+        // a white diffuse sphere vanishes under a uniform sky, Russian
+        // roulette from the first bounce only adds noise
+        let mut world = HittableList::new();
+        world.background = Background::Color(Color::new_rgb(0.5, 0.5, 0.5));
+        world.add(Object::Sphere {
+            center: Point(Vec3::iso(0.0)),
+            radius: 1.0,
+            material: Material::new_lambertian(Color::new_rgb(1.0, 1.0, 1.0)),
+            moving_component: None,
+        });
+        let integrator = Integrator {
+            russian_roulette_depth: 0,
+            ..Integrator::default()
+        };
+        let average = furnace(&world, &integrator, 50_000);
+        assert!((average.rgb.x - 0.5).abs() < 0.01, "{:?}", average);
+    }
 }
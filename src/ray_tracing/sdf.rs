@@ -0,0 +1,283 @@
+use std::sync::Arc;
+
+use super::geom::*;
+use super::ray::*;
+
+/// Signed distance function, negative inside the surface.
+///
+/// Distances may be underestimated, which only slows down sphere tracing,
+/// but not overestimated or rays step through the surface.
+#[derive(Clone)]
+pub enum Sdf {
+    Sphere {
+        radius: f32,
+    },
+    /// Box centred on the origin.
+    Cuboid {
+        half_extent: Vec3,
+    },
+    /// Torus around the `y` axis.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// `shape` grown by `radius`, rounding its edges.
+    Rounded {
+        shape: Box<Sdf>,
+        radius: f32,
+    },
+    Translate {
+        shape: Box<Sdf>,
+        offset: Vec3,
+    },
+    /// `shape` repeated every `period` along each axis with a non zero
+    /// period. Copies should fit in their cell for the distance to hold.
+    Repeat {
+        shape: Box<Sdf>,
+        period: Vec3,
+    },
+    /// Blends the shapes over `smoothness`, sharp when zero.
+    Union {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        smoothness: f32,
+    },
+    Intersection {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        smoothness: f32,
+    },
+    /// `a` with `b` carved out of it.
+    Subtraction {
+        a: Box<Sdf>,
+        b: Box<Sdf>,
+        smoothness: f32,
+    },
+    Custom(Arc<dyn Fn(&Vec3) -> f32 + Send + Sync>),
+}
+
+impl Sdf {
+    // distance under which the surface counts as hit
+    const EPSILON: f32 = 1e-4;
+    const MAX_STEPS: u32 = 512;
+
+    pub fn sphere(radius: f32) -> Sdf {
+        Sdf::Sphere { radius }
+    }
+
+    pub fn cuboid(half_extent: Vec3) -> Sdf {
+        Sdf::Cuboid { half_extent }
+    }
+
+    pub fn torus(major_radius: f32, minor_radius: f32) -> Sdf {
+        Sdf::Torus {
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn custom<F: Fn(&Vec3) -> f32 + Send + Sync + 'static>(distance: F) -> Sdf {
+        Sdf::Custom(Arc::new(distance))
+    }
+
+    pub fn rounded(self, radius: f32) -> Sdf {
+        Sdf::Rounded {
+            shape: Box::new(self),
+            radius,
+        }
+    }
+
+    pub fn translate(self, offset: Vec3) -> Sdf {
+        Sdf::Translate {
+            shape: Box::new(self),
+            offset,
+        }
+    }
+
+    pub fn repeat(self, period: Vec3) -> Sdf {
+        Sdf::Repeat {
+            shape: Box::new(self),
+            period,
+        }
+    }
+
+    pub fn union(self, other: Sdf, smoothness: f32) -> Sdf {
+        Sdf::Union {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+
+    pub fn intersection(self, other: Sdf, smoothness: f32) -> Sdf {
+        Sdf::Intersection {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+
+    pub fn subtraction(self, other: Sdf, smoothness: f32) -> Sdf {
+        Sdf::Subtraction {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+
+    pub fn distance(&self, p: &Vec3) -> f32 {
+        match self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::Cuboid { half_extent } => {
+                let q = Vec3::new(
+                    p.x.abs() - half_extent.x,
+                    p.y.abs() - half_extent.y,
+                    p.z.abs() - half_extent.z,
+                );
+                let outside = q.max(&Vec3::iso(0.0)).length();
+                outside + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            Sdf::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            Sdf::Rounded { shape, radius } => shape.distance(p) - radius,
+            Sdf::Translate { shape, offset } => shape.distance(&(p - offset)),
+            Sdf::Repeat { shape, period } => {
+                let wrap = |x: f32, period: f32| {
+                    if period > 0.0 {
+                        x - period * (x / period).round()
+                    } else {
+                        x
+                    }
+                };
+                shape.distance(&Vec3::new(
+                    wrap(p.x, period.x),
+                    wrap(p.y, period.y),
+                    wrap(p.z, period.z),
+                ))
+            }
+            Sdf::Union { a, b, smoothness } => {
+                smooth_min(a.distance(p), b.distance(p), *smoothness)
+            }
+            Sdf::Intersection { a, b, smoothness } => {
+                -smooth_min(-a.distance(p), -b.distance(p), *smoothness)
+            }
+            Sdf::Subtraction { a, b, smoothness } => {
+                -smooth_min(-a.distance(p), b.distance(p), *smoothness)
+            }
+            Sdf::Custom(distance) => distance(p),
+        }
+    }
+
+    /// Outward normal at `p`, the gradient by central differences.
+    pub fn normal(&self, p: &Vec3) -> Vec3 {
+        let h = Sdf::EPSILON;
+        let d = |x: f32, y: f32, z: f32| self.distance(&Vec3::new(p.x + x, p.y + y, p.z + z));
+        Vec3::new(
+            d(h, 0.0, 0.0) - d(-h, 0.0, 0.0),
+            d(0.0, h, 0.0) - d(0.0, -h, 0.0),
+            d(0.0, 0.0, h) - d(0.0, 0.0, -h),
+        )
+        .unit_norm()
+    }
+
+    /// Sphere traces `ray` over `[t_min, t_max]` within `bounds`, giving
+    /// the first `t` on the surface. Works from inside the surface too,
+    /// stepping by the absolute distance.
+    ///
+    /// Rays starting on the surface, like the ones leaving it, have to get
+    /// `EPSILON` away from it before they can hit, or grazing ones would
+    /// find their own origin again.
+    pub fn trace(&self, ray: &Ray, t_min: f32, t_max: f32, bounds: &Aabb) -> Option<f32> {
+        let (mut t, t_max) = bounds.overlap(&ray.origin.0, &ray.direction.0, t_min, t_max)?;
+        let length = ray.direction.0.length();
+        let mut left_start = false;
+        for _ in 0..Sdf::MAX_STEPS {
+            let distance = self.distance(&ray.at(t).0).abs();
+            if distance >= Sdf::EPSILON {
+                left_start = true;
+            } else if left_start {
+                return Some(t);
+            }
+            t += distance.max(Sdf::EPSILON) / length;
+            if t > t_max {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+// polynomial smooth minimum, `min` when `k` is zero
+fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b * (1.0 - h) + a * h - k * h * (1.0 - h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray_tracing::rand::Random;
+
+    #[test]
+    fn test_distances() {
+        let p = Vec3::new(3.0, 0.0, 0.0);
+        assert_eq!(Sdf::sphere(1.0).distance(&p), 2.0);
+        assert_eq!(Sdf::cuboid(Vec3::iso(1.0)).distance(&p), 2.0);
+        assert_eq!(Sdf::torus(2.0, 0.5).distance(&p), 0.5);
+        let carved = Sdf::sphere(2.0).subtraction(Sdf::sphere(1.0), 0.0);
+        assert_eq!(carved.distance(&Vec3::iso(0.0)), 1.0);
+        let repeated = Sdf::sphere(1.0).repeat(Vec3::new(10.0, 0.0, 0.0));
+        assert_eq!(repeated.distance(&Vec3::new(21.0, 0.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn test_trace_sphere() {
+        let sphere = Sdf::sphere(1.0).translate(Vec3::new(0.0, 0.0, -5.0));
+        let bounds = Aabb::new(Point(Vec3::iso(-10.0)), Point(Vec3::iso(10.0)));
+        let ray = Ray::new(Point(Vec3::iso(0.0)), Point(Vec3::new(0.0, 0.0, -2.0)), 0.0);
+        let t = sphere.trace(&ray, 0.001, INFINITY, &bounds).unwrap();
+        assert!((t - 2.0).abs() < 1e-3, "{}", t);
+        let normal = sphere.normal(&ray.at(t).0);
+        assert!((&normal - &Vec3::new(0.0, 0.0, 1.0)).length() < 1e-3);
+        // from the inside, to the far side
+        let inside = Ray::new(ray.at(2.5), ray.direction.clone(), 0.0);
+        let t = sphere.trace(&inside, 0.001, INFINITY, &bounds).unwrap();
+        assert!((t - 0.5).abs() < 1e-3, "{}", t);
+    }
+
+    #[test]
+    fn test_grazing_rays_leave_the_surface() {
+        let sphere = Sdf::sphere(1.0);
+        let bounds = Aabb::new(Point(Vec3::iso(-2.0)), Point(Vec3::iso(2.0)));
+        let p = Point(Vec3::new(1.0, 0.0, 0.0));
+        let leaving = Ray::new(p.clone(), Point(Vec3::new(0.05, 1.0, 0.0)), 0.0);
+        assert_eq!(sphere.trace(&leaving, 0.001, INFINITY, &bounds), None);
+        // just under the surface, to the far end of the chord
+        let entering = Ray::new(p, Point(Vec3::new(-0.05, 1.0, 0.0).unit_norm()), 0.0);
+        let t = sphere.trace(&entering, 0.001, INFINITY, &bounds).unwrap();
+        assert!((t - 0.1).abs() < 0.01, "{}", t);
+        // and from all around
+        let mut r = Random::seeded(1);
+        for _ in 0..2000 {
+            let normal = Vec3::random_unit_vector(&mut r);
+            let direction = Vec3::random_unit_vector(&mut r);
+            let cosine = direction.dot(&normal);
+            let ray = Ray::new(Point(normal), Point(direction), 0.0);
+            let hit = sphere.trace(&ray, 0.001, INFINITY, &bounds);
+            if cosine > 0.0 {
+                assert_eq!(hit, None, "{}", cosine);
+            } else if cosine < -0.05 {
+                let chord = -2.0 * cosine;
+                assert!((hit.unwrap() - chord).abs() < 0.01, "{} {:?}", chord, hit);
+            }
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::sync::OnceLock;
+
+use super::color::*;
+use super::geom::*;
+use super::rand::*;
+
+pub const LAMBDA_MIN: f32 = 360.0;
+pub const LAMBDA_MAX: f32 = 830.0;
+
+/// Wavelengths in nanometres carried by a spectral path. The first one is
+/// the hero wavelength, the others are evenly rotated from it, and the
+/// three channels of a spectral `Color` hold the values at each of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wavelengths {
+    pub lambda: [f32; 3],
+}
+
+impl Wavelengths {
+    pub fn sample(r: &mut Random) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = r.random_double() * range;
+        let mut lambda = [0.0; 3];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            *l = LAMBDA_MIN + (hero + range * i as f32 / 3.0) % range;
+        }
+        Wavelengths { lambda }
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// Values at the wavelengths of a smooth spectrum with the colour `rgb`.
+    /// Upsampling is linear, so scaled colours give scaled spectra.
+    pub fn upsample(&self, rgb: &Color) -> Color {
+        let tables = tables();
+        let coefficients = tables.rgb_to_basis.transform_vector(&rgb.rgb);
+        let value = |lambda: f32| {
+            let b = basis(lambda);
+            (coefficients.x * b[0] + coefficients.y * b[1] + coefficients.z * b[2]).max(0.0)
+        };
+        Color::new_rgb(
+            value(self.lambda[0]),
+            value(self.lambda[1]),
+            value(self.lambda[2]),
+        )
+    }
+
+    /// Colour of the spectral estimate `values`, through the CIE matching
+    /// functions to XYZ and then to linear sRGB. White balanced so that a
+    /// flat spectrum is white.
+    pub fn to_rgb(&self, values: &Color) -> Color {
+        let tables = tables();
+        let channels = [values.rgb.x, values.rgb.y, values.rgb.z];
+        // each wavelength is drawn with density 1 / range
+        let scale = (LAMBDA_MAX - LAMBDA_MIN) / (3.0 * tables.y_integral);
+        let mut xyz = Vec3::iso(0.0);
+        for (lambda, value) in self.lambda.iter().zip(channels.iter()) {
+            xyz += cie_xyz(*lambda).scalar_mul(value * scale);
+        }
+        let rgb = xyz_to_rgb(&xyz);
+        Color::new_rgb(
+            rgb.x / tables.white.x,
+            rgb.y / tables.white.y,
+            rgb.z / tables.white.z,
+        )
+    }
+}
+
+/// `rgb` as spectral values when tracing with `wavelengths`, unchanged
+/// otherwise.
+pub fn upsample(wavelengths: Option<&Wavelengths>, rgb: Color) -> Color {
+    match wavelengths {
+        Some(wavelengths) => wavelengths.upsample(&rgb),
+        None => rgb,
+    }
+}
+
+/// Spectral radiance of a black body at `kelvin`, in W/(m² sr nm).
+pub fn planck(lambda: f32, kelvin: f32) -> f32 {
+    if kelvin <= 0.0 {
+        return 0.0;
+    }
+    // 2hc² in W nm⁴ / (m² sr) and hc / k in nm K
+    const C1: f64 = 1.191_043e20;
+    const C2: f64 = 1.438_777e7;
+    let lambda = lambda as f64;
+    (C1 / (lambda.powi(5) * ((C2 / (lambda * kelvin as f64)).exp_m1()))) as f32
+}
+
+/// Black body radiance at `kelvin` as spectral values when tracing with
+/// `wavelengths`, or its colour otherwise.
+pub fn blackbody(wavelengths: Option<&Wavelengths>, kelvin: f32) -> Color {
+    match wavelengths {
+        Some(wavelengths) => {
+            let [a, b, c] = wavelengths.lambda.map(|lambda| planck(lambda, kelvin));
+            Color::new_rgb(a, b, c)
+        }
+        None => blackbody_rgb(kelvin),
+    }
+}
+
+// colours in steps of 100K, interpolated in between
+fn blackbody_rgb(kelvin: f32) -> Color {
+    const STEP: f32 = 100.0;
+    static TABLE: OnceLock<Vec<Color>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let tables = tables();
+        (0..=400)
+            .map(|i| {
+                let kelvin = i as f32 * STEP;
+                let mut xyz = Vec3::iso(0.0);
+                for l in LAMBDA_MIN as u32..LAMBDA_MAX as u32 {
+                    let lambda = l as f32 + 0.5;
+                    xyz += cie_xyz(lambda).scalar_mul(planck(lambda, kelvin));
+                }
+                let rgb = xyz_to_rgb(&xyz.scalar_mul(1.0 / tables.y_integral));
+                Color::new_rgb(
+                    rgb.x / tables.white.x,
+                    rgb.y / tables.white.y,
+                    rgb.z / tables.white.z,
+                )
+            })
+            .collect()
+    });
+    let x = (kelvin.max(0.0) / STEP).min((table.len() - 1) as f32);
+    let i = (x as usize).min(table.len() - 2);
+    let t = x - i as f32;
+    table[i].scalar_mul(1.0 - t) + table[i + 1].scalar_mul(t)
+}
+
+/// Refractive index as a function of wavelength.
+#[derive(Debug, Clone)]
+pub enum Ior {
+    Constant(f32),
+    /// `a + b / λ²` with `λ` in micrometres.
+    Cauchy {
+        a: f32,
+        b: f32,
+    },
+    /// `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)` with `λ` in micrometres.
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+}
+
+impl Ior {
+    /// Wavelength of the helium d line, where catalogue indices are given
+    /// and where dispersive materials are evaluated when rendering in RGB.
+    pub const D_LINE: f32 = 587.56;
+
+    /// Schott N-BK7 crown glass.
+    pub fn bk7() -> Ior {
+        Ior::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        }
+    }
+
+    pub fn diamond() -> Ior {
+        Ior::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030_625, 0.011_236, 0.0],
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+
+    pub fn at(&self, lambda: f32) -> f32 {
+        let micrometres = lambda / 1000.0;
+        let l2 = micrometres * micrometres;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let n2: f32 = 1.0
+                    + b.iter()
+                        .zip(c.iter())
+                        .map(|(b, c)| b * l2 / (l2 - c))
+                        .sum::<f32>();
+                n2.sqrt()
+            }
+        }
+    }
+}
+
+/// CIE 1931 standard observer, multi-lobe fit of Wyman, Sloan and Shirley.
+pub fn cie_xyz(lambda: f32) -> Vec3 {
+    let g = |mu: f32, sigma_1: f32, sigma_2: f32| {
+        let sigma = if lambda < mu { sigma_1 } else { sigma_2 };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// XYZ to linear sRGB primaries.
+pub fn xyz_to_rgb(xyz: &Vec3) -> Vec3 {
+    Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+// smooth blue, green and red bands summing to one, upsampled spectra are
+// combinations of them
+fn basis(lambda: f32) -> [f32; 3] {
+    let blue = 1.0 / (1.0 + ((lambda - 490.0) / 10.0).exp());
+    let red = 1.0 / (1.0 + ((590.0 - lambda) / 10.0).exp());
+    [blue, 1.0 - blue - red, red]
+}
+
+struct Tables {
+    y_integral: f32,
+    // sRGB of the flat spectrum, before white balance
+    white: Vec3,
+    rgb_to_basis: Mat4,
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        // 1nm Riemann sums over the visible range
+        let lambdas = (LAMBDA_MIN as u32..LAMBDA_MAX as u32).map(|l| l as f32 + 0.5);
+        let y_integral: f32 = lambdas.clone().map(|l| cie_xyz(l).y).sum();
+        let mut xyz_flat = Vec3::iso(0.0);
+        let mut xyz_basis = [Vec3::iso(0.0), Vec3::iso(0.0), Vec3::iso(0.0)];
+        for l in lambdas {
+            let xyz = cie_xyz(l);
+            xyz_flat = &xyz_flat + &xyz;
+            for (acc, b) in xyz_basis.iter_mut().zip(basis(l).iter()) {
+                *acc = &*acc + &xyz.scalar_mul(*b);
+            }
+        }
+        let white = xyz_to_rgb(&xyz_flat.scalar_mul(1.0 / y_integral));
+        // columns are the white balanced colours of the basis bands
+        let mut basis_to_rgb = Mat4::identity();
+        for (j, xyz) in xyz_basis.iter().enumerate() {
+            let rgb = xyz_to_rgb(&xyz.scalar_mul(1.0 / y_integral));
+            basis_to_rgb.0[0][j] = rgb.x / white.x;
+            basis_to_rgb.0[1][j] = rgb.y / white.y;
+            basis_to_rgb.0[2][j] = rgb.z / white.z;
+        }
+        Tables {
+            y_integral,
+            white,
+            rgb_to_basis: basis_to_rgb
+                .inverse()
+                .expect("spectral basis colours are independent"),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgb_round_trip() {
+        // averaged over the spectrum, upsampling then converting back gives
+        // the colour again
+        let color = Color::new_rgb(0.2, 0.5, 0.8);
+        let mut sum = Color::zero();
+        let steps = 470;
+        for i in 0..steps {
+            let hero = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * (i as f32 + 0.5) / steps as f32;
+            let wavelengths = Wavelengths {
+                lambda: [hero, hero, hero],
+            };
+            sum += wavelengths.to_rgb(&wavelengths.upsample(&color));
+        }
+        let mean = sum.scalar_mul(1.0 / steps as f32);
+        assert!((&mean.rgb - &color.rgb).length() < 0.01, "{:?}", mean);
+    }
+
+    #[test]
+    fn test_bk7_dispersion() {
+        let ior = Ior::bk7();
+        assert!((ior.at(Ior::D_LINE) - 1.5168).abs() < 1e-3);
+        assert!(ior.at(450.0) > ior.at(650.0));
+    }
+
+    #[test]
+    fn test_planck_wien_peak() {
+        // peak at Wien's displacement wavelength, 2.898e6 nm K / T
+        let kelvin = 5000.0;
+        let peak = (300..1000)
+            .map(|l| l as f32)
+            .max_by(|a, b| planck(*a, kelvin).total_cmp(&planck(*b, kelvin)))
+            .unwrap();
+        assert!((peak - 2.898e6 / kelvin).abs() < 2.0, "{}", peak);
+        let rgb = blackbody(None, 2000.0);
+        assert!(rgb.rgb.x > rgb.rgb.y && rgb.rgb.y > rgb.rgb.z);
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use super::color::*;
+use super::geom::*;
+use super::image::Image;
+
+/// Colour varying over a surface, looked up from the hit coordinates.
+#[derive(Clone)]
+pub enum Texture {
+    Solid(Color),
+    /// Solid checkerboard of cubes `scale` wide.
+    Checker {
+        scale: f32,
+        even: Color,
+        odd: Color,
+    },
+    /// Image stretched over `(u, v)`, `v` going up, filtered bilinearly.
+    Image(Arc<Image>),
+}
+
+impl Texture {
+    /// Grey texture, for masks and other scalar parameters.
+    pub fn constant(value: f32) -> Texture {
+        Texture::Solid(Color::new_rgb(value, value, value))
+    }
+
+    pub fn value(&self, u: f32, v: f32, p: &Point) -> Color {
+        match self {
+            Texture::Solid(color) => color.clone(),
+            Texture::Checker { scale, even, odd } => {
+                let cell = |x: f32| (x / scale).floor() as i64;
+                if (cell(p.x) + cell(p.y) + cell(p.z)).rem_euclid(2) == 0 {
+                    even.clone()
+                } else {
+                    odd.clone()
+                }
+            }
+            Texture::Image(image) => {
+                // bilinear between pixel centres, so bump maps have slopes
+                let x = (u.clamp(0.0, 1.0) * image.width as f32 - 0.5).max(0.0);
+                let y = ((1.0 - v.clamp(0.0, 1.0)) * image.height as f32 - 0.5).max(0.0);
+                let (x0, y0) = (x as usize, y as usize);
+                let (fx, fy) = (x.fract(), y.fract());
+                let top = image.pixel(x0, y0).scalar_mul(1.0 - fx)
+                    + image.pixel(x0 + 1, y0).scalar_mul(fx);
+                let bottom = image.pixel(x0, y0 + 1).scalar_mul(1.0 - fx)
+                    + image.pixel(x0 + 1, y0 + 1).scalar_mul(fx);
+                Color::new(top.scalar_mul(1.0 - fy) + bottom.scalar_mul(fy))
+            }
+        }
+    }
+
+    /// Mean of the channels of `value`.
+    pub fn scalar(&self, u: f32, v: f32, p: &Point) -> f32 {
+        let color = self.value(u, v, p);
+        (color.rgb.x + color.rgb.y + color.rgb.z) / 3.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_lookup() {
+        // top row black, bottom row white
+        let image = Image::new(1, 2, vec![Vec3::iso(0.0), Vec3::iso(1.0)]);
+        let texture = Texture::Image(Arc::new(image));
+        let p = Point(Vec3::iso(0.0));
+        assert_eq!(texture.scalar(0.5, 0.9, &p), 0.0);
+        assert_eq!(texture.scalar(0.5, 0.1, &p), 1.0);
+        assert_eq!(texture.scalar(0.5, 1.0, &p), 0.0);
+    }
+}
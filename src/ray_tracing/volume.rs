@@ -0,0 +1,234 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use super::geom::*;
+
+/// Scalar field on a regular 3D grid, like the density or temperature of a
+/// smoke simulation. Values are at voxel centres, `x` varies fastest.
+///
+/// Grids are read from headerless little endian `f32` data with the size
+/// given separately, or from files with the size in a header:
+///
+/// ```text
+/// b"VOX1"                 magic
+/// nx, ny, nz              little endian u32
+/// nx * ny * nz values     little endian f32
+/// ```
+pub struct VoxelGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    storage: Storage,
+    max: f32,
+}
+
+enum Storage {
+    Dense(Vec<f32>),
+    /// Bricks of `BRICK³` voxels, `None` where all of them are zero.
+    Sparse(Vec<Option<Vec<f32>>>),
+}
+
+const BRICK: usize = 8;
+
+impl VoxelGrid {
+    /// Panics if `values` is empty or not `nx * ny * nz` long. Negative
+    /// values are clamped to zero.
+    pub fn new(nx: usize, ny: usize, nz: usize, mut values: Vec<f32>) -> VoxelGrid {
+        assert!(nx > 0 && ny > 0 && nz > 0, "empty grid");
+        assert_eq!(values.len(), nx * ny * nz, "wrong number of voxels");
+        for value in &mut values {
+            *value = value.max(0.0);
+        }
+        let max = values.iter().cloned().fold(0.0, f32::max);
+        VoxelGrid {
+            nx,
+            ny,
+            nz,
+            storage: Storage::Dense(values),
+            max,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<VoxelGrid> {
+        VoxelGrid::parse(&fs::read(path)?)
+    }
+
+    pub fn load_raw<P: AsRef<Path>>(path: P, nx: usize, ny: usize, nz: usize) -> Result<VoxelGrid> {
+        VoxelGrid::parse_raw(&fs::read(path)?, nx, ny, nz)
+    }
+
+    /// Reads the `VOX1` format.
+    pub fn parse(bytes: &[u8]) -> Result<VoxelGrid> {
+        if bytes.get(0..4) != Some(b"VOX1".as_slice()) {
+            return Err(invalid("not a VOX1 grid"));
+        }
+        let header = bytes.get(4..16).ok_or_else(|| invalid("truncated grid"))?;
+        let size: Vec<usize> = header
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as usize)
+            .collect();
+        VoxelGrid::parse_raw(&bytes[16..], size[0], size[1], size[2])
+    }
+
+    /// Reads `nx * ny * nz` little endian `f32`, ignoring anything after.
+    pub fn parse_raw(bytes: &[u8], nx: usize, ny: usize, nz: usize) -> Result<VoxelGrid> {
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(invalid("empty grid"));
+        }
+        let length = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .and_then(|n| n.checked_mul(4))
+            .ok_or_else(|| invalid("grid too large"))?;
+        let data = bytes
+            .get(..length)
+            .ok_or_else(|| invalid("truncated grid"))?;
+        let values = data
+            .chunks(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        Ok(VoxelGrid::new(nx, ny, nz, values))
+    }
+
+    /// Same grid storing only the bricks with non zero voxels, for
+    /// simulations where most of the domain is empty.
+    pub fn sparse(self) -> VoxelGrid {
+        let values = match &self.storage {
+            Storage::Dense(values) => values,
+            Storage::Sparse(_) => return self,
+        };
+        let bricks_x = self.nx.div_ceil(BRICK);
+        let bricks_y = self.ny.div_ceil(BRICK);
+        let bricks_z = self.nz.div_ceil(BRICK);
+        let mut bricks = Vec::with_capacity(bricks_x * bricks_y * bricks_z);
+        for bz in 0..bricks_z {
+            for by in 0..bricks_y {
+                for bx in 0..bricks_x {
+                    let mut brick = vec![0.0; BRICK * BRICK * BRICK];
+                    let mut empty = true;
+                    for (i, voxel) in brick.iter_mut().enumerate() {
+                        let x = bx * BRICK + i % BRICK;
+                        let y = by * BRICK + i / BRICK % BRICK;
+                        let z = bz * BRICK + i / (BRICK * BRICK);
+                        if x < self.nx && y < self.ny && z < self.nz {
+                            *voxel = values[(z * self.ny + y) * self.nx + x];
+                            empty &= *voxel == 0.0;
+                        }
+                    }
+                    bricks.push(if empty { None } else { Some(brick) });
+                }
+            }
+        }
+        VoxelGrid {
+            storage: Storage::Sparse(bricks),
+            ..self
+        }
+    }
+
+    /// Largest value, bounding the field for delta tracking.
+    pub fn max_value(&self) -> f32 {
+        self.max
+    }
+
+    /// Voxel value, clamped to the grid.
+    pub fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        let (x, y, z) = (x.min(self.nx - 1), y.min(self.ny - 1), z.min(self.nz - 1));
+        match &self.storage {
+            Storage::Dense(values) => values[(z * self.ny + y) * self.nx + x],
+            Storage::Sparse(bricks) => {
+                let bricks_x = self.nx.div_ceil(BRICK);
+                let bricks_y = self.ny.div_ceil(BRICK);
+                let index = (z / BRICK * bricks_y + y / BRICK) * bricks_x + x / BRICK;
+                match &bricks[index] {
+                    Some(brick) => brick[((z % BRICK) * BRICK + y % BRICK) * BRICK + x % BRICK],
+                    None => 0.0,
+                }
+            }
+        }
+    }
+
+    /// Trilinear interpolation at `p` in `[0, 1]³` over the whole grid,
+    /// zero outside.
+    pub fn lookup(&self, p: &Vec3) -> f32 {
+        if [p.x, p.y, p.z].iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return 0.0;
+        }
+        // between voxel centres, clamped at the faces
+        let x = (p.x * self.nx as f32 - 0.5).max(0.0);
+        let y = (p.y * self.ny as f32 - 0.5).max(0.0);
+        let z = (p.z * self.nz as f32 - 0.5).max(0.0);
+        let (x0, y0, z0) = (x as usize, y as usize, z as usize);
+        let (fx, fy, fz) = (x.fract(), y.fract(), z.fract());
+        let lerp = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
+        let plane = |z: usize| {
+            let bottom = lerp(self.voxel(x0, y0, z), self.voxel(x0 + 1, y0, z), fx);
+            let top = lerp(self.voxel(x0, y0 + 1, z), self.voxel(x0 + 1, y0 + 1, z), fx);
+            lerp(bottom, top, fy)
+        };
+        lerp(plane(z0), plane(z0 + 1), fz)
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let mut bytes = b"VOX1".to_vec();
+        for n in [2u32, 1, 1] {
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        for v in [0.0f32, 1.0] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        let grid = VoxelGrid::parse(&bytes).unwrap();
+        assert_eq!(grid.max_value(), 1.0);
+        assert_eq!(grid.lookup(&Vec3::new(0.5, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.lookup(&Vec3::new(0.1, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.lookup(&Vec3::new(1.5, 0.5, 0.5)), 0.0);
+        assert!(VoxelGrid::parse(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn test_reject_bad_sizes() {
+        let header = |size: [u32; 3]| {
+            let mut bytes = b"VOX1".to_vec();
+            for n in size {
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0; 64]);
+            bytes
+        };
+        for size in [[0, 1, 1], [4, 0, 4], [u32::MAX, u32::MAX, u32::MAX]] {
+            let error = VoxelGrid::parse(&header(size)).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+        assert!(VoxelGrid::parse_raw(&[0; 64], usize::MAX, 2, 1).is_err());
+        // negative densities would break the majorant
+        let grid = VoxelGrid::new(2, 1, 1, vec![-3.0, -1.0]);
+        assert_eq!(grid.voxel(0, 0, 0), 0.0);
+        assert_eq!(grid.max_value(), 0.0);
+    }
+
+    #[test]
+    fn test_sparse_matches_dense() {
+        let n = 10;
+        let values = (0..n * n * n)
+            .map(|i| if i % 97 < 5 { i as f32 } else { 0.0 })
+            .collect();
+        let dense = VoxelGrid::new(n, n, n, values);
+        let expected: Vec<f32> = (0..n * n * n)
+            .map(|i| dense.voxel(i % n, i / n % n, i / (n * n)))
+            .collect();
+        let sparse = dense.sparse();
+        for (i, value) in expected.iter().enumerate() {
+            assert_eq!(sparse.voxel(i % n, i / n % n, i / (n * n)), *value);
+        }
+    }
+}